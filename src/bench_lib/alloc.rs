@@ -0,0 +1,183 @@
+//! Heap allocation counting, enabled through the `count-allocations` feature
+//!
+//! When the feature is enabled, a counting wrapper around the system allocator is installed as the
+//! global allocator. Every benchmark process then writes its allocation statistics to a file,
+//! which the top-level run reads back to report them next to the instruction counts. Note that
+//! the bookkeeping adds a few instructions to every allocation, so instruction counts obtained
+//! with the feature enabled are not comparable to those obtained without it.
+//!
+//! Like instructions, allocations are only counted while instrumentation is running, so the parts
+//! of a benchmark excluded through [`stop_instrumentation`](super::stop_instrumentation) are left
+//! out of the statistics too. The amount of allocated bytes is tracked throughout, though, so
+//! the peak and retained bytes always reflect the real heap usage.
+
+#[cfg(feature = "count-allocations")]
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+#[cfg(feature = "count-allocations")]
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static DEALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static REALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static CURRENT_BYTES: AtomicU64 = AtomicU64::new(0);
+static PEAK_BYTES: AtomicU64 = AtomicU64::new(0);
+static COUNTING: AtomicBool = AtomicBool::new(true);
+
+/// Returns true if allocations are being counted
+pub fn enabled() -> bool {
+    cfg!(feature = "count-allocations")
+}
+
+/// Pauses or resumes counting, following the instrumentation state
+pub(super) fn set_counting(counting: bool) {
+    COUNTING.store(counting, Ordering::Relaxed);
+    if counting {
+        PEAK_BYTES.fetch_max(CURRENT_BYTES.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+}
+
+/// Keeps the value alive until the process exits
+///
+/// The heap used by the value is then reported as retained bytes, which makes it possible to
+/// measure how much memory a long-lived object occupies.
+pub fn retain<T>(value: T) {
+    std::mem::forget(value);
+}
+
+/// Returns the path of the file holding the allocation statistics of the given benchmark
+pub fn stats_path(name: &str) -> PathBuf {
+    PathBuf::from(format!("target/cachegrind/allocations.out.{}", name))
+}
+
+/// Heap usage of a benchmark process
+#[derive(Clone, Copy, Default)]
+pub struct AllocStats {
+    pub allocations: u64,
+    pub deallocations: u64,
+    pub reallocations: u64,
+    /// The highest amount of bytes allocated at the same time
+    pub peak_bytes: u64,
+    /// The amount of bytes still allocated when the statistics were taken
+    pub retained_bytes: u64,
+}
+
+impl AllocStats {
+    /// Returns the statistics of the current process so far
+    pub fn current() -> Self {
+        Self {
+            allocations: ALLOCATIONS.load(Ordering::Relaxed),
+            deallocations: DEALLOCATIONS.load(Ordering::Relaxed),
+            reallocations: REALLOCATIONS.load(Ordering::Relaxed),
+            peak_bytes: PEAK_BYTES.load(Ordering::Relaxed),
+            retained_bytes: CURRENT_BYTES.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Subtracts the statistics of another run, typically the setup of a benchmark
+    ///
+    /// For peak bytes, the result is the amount by which the peak exceeds the other run's peak.
+    pub fn subtract(&self, other: &Self) -> Self {
+        Self {
+            allocations: self.allocations.saturating_sub(other.allocations),
+            deallocations: self.deallocations.saturating_sub(other.deallocations),
+            reallocations: self.reallocations.saturating_sub(other.reallocations),
+            peak_bytes: self.peak_bytes.saturating_sub(other.peak_bytes),
+            retained_bytes: self.retained_bytes.saturating_sub(other.retained_bytes),
+        }
+    }
+
+    pub fn write(&self, path: &Path) {
+        let contents = format!(
+            "{} {} {} {} {}",
+            self.allocations,
+            self.deallocations,
+            self.reallocations,
+            self.peak_bytes,
+            self.retained_bytes
+        );
+        fs::write(path, contents).expect("Unable to write allocation statistics");
+    }
+
+    pub fn read(path: &Path) -> Self {
+        let contents = fs::read_to_string(path).expect("Unable to read allocation statistics");
+        let values: Vec<u64> = contents
+            .split_whitespace()
+            .map(|value| value.parse().expect("Invalid allocation statistics"))
+            .collect();
+
+        match values[..] {
+            [allocations, deallocations, reallocations, peak_bytes, retained_bytes] => Self {
+                allocations,
+                deallocations,
+                reallocations,
+                peak_bytes,
+                retained_bytes,
+            },
+            _ => panic!("Invalid allocation statistics in {}", path.display()),
+        }
+    }
+}
+
+/// Wraps the system allocator, keeping track of allocations and allocated bytes
+#[cfg(feature = "count-allocations")]
+pub struct CountingAllocator;
+
+#[cfg(feature = "count-allocations")]
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            count(&ALLOCATIONS);
+            add_bytes(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            count(&ALLOCATIONS);
+            add_bytes(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        count(&DEALLOCATIONS);
+        CURRENT_BYTES.fetch_sub(layout.size() as u64, Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            count(&REALLOCATIONS);
+            if new_size > layout.size() {
+                add_bytes(new_size - layout.size());
+            } else {
+                CURRENT_BYTES.fetch_sub((layout.size() - new_size) as u64, Ordering::Relaxed);
+            }
+        }
+        new_ptr
+    }
+}
+
+#[cfg(feature = "count-allocations")]
+fn count(counter: &AtomicU64) {
+    if COUNTING.load(Ordering::Relaxed) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(feature = "count-allocations")]
+fn add_bytes(size: usize) {
+    let current = CURRENT_BYTES.fetch_add(size as u64, Ordering::Relaxed) + size as u64;
+    if COUNTING.load(Ordering::Relaxed) {
+        PEAK_BYTES.fetch_max(current, Ordering::Relaxed);
+    }
+}
@@ -14,6 +14,14 @@ pub enum ReportingMode {
     AllInstructionsExceptSetup(String),
 }
 
+/// An amount of work performed by a benchmark, used to report instructions per unit of work
+pub struct WorkUnit {
+    /// The name of the unit, e.g. `byte`
+    pub name: &'static str,
+    /// The amount of units of work performed by the benchmark
+    pub count: u64,
+}
+
 pub struct Benchmark {
     /// The name of the benchmark, as shown in the benchmark results
     name: String,
@@ -21,6 +29,8 @@ pub struct Benchmark {
     function: Box<dyn Fn() + Send + Sync>,
     /// The way instructions should be reported for this benchmark
     reporting_mode: ReportingMode,
+    /// Units of work by which the reported instruction count should additionally be divided
+    work_units: Vec<WorkUnit>,
 }
 
 impl Benchmark {
@@ -29,6 +39,7 @@ impl Benchmark {
             name: name.into(),
             function: Box::new(function),
             reporting_mode: ReportingMode::AllInstructions,
+            work_units: Vec::new(),
         }
     }
 
@@ -42,6 +53,18 @@ impl Benchmark {
         self
     }
 
+    /// Additionally report the instruction count divided by `count` units of work
+    ///
+    /// Can be called multiple times to report the instruction count per different units.
+    pub fn report_per(mut self, name: &'static str, count: u64) -> Self {
+        assert!(
+            count > 0,
+            "cannot report instructions per zero units of work"
+        );
+        self.work_units.push(WorkUnit { name, count });
+        self
+    }
+
     pub fn name(&self) -> &str {
         &self.name
     }
@@ -53,6 +76,10 @@ impl Benchmark {
     pub fn reporting_mode(&self) -> &ReportingMode {
         &self.reporting_mode
     }
+
+    pub fn work_units(&self) -> &[WorkUnit] {
+        &self.work_units
+    }
 }
 
 /// Panics if the benchmarks are invalid
@@ -3,30 +3,60 @@ use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 
+/// The first valgrind version whose cachegrind supports starting and stopping instrumentation
+const MIN_VALGRIND_VERSION: (u32, u32) = (3, 22);
+
 pub fn check_valgrind() -> bool {
     let result = Command::new("valgrind")
         .arg("--tool=cachegrind")
         .arg("--version")
-        .stdout(Stdio::null())
         .stderr(Stdio::null())
-        .status();
+        .output();
 
-    match result {
+    let output = match result {
         Err(e) => {
             println!("Unexpected error while launching valgrind. Error: {}", e);
-            false
+            return false;
         }
-        Ok(status) => {
-            if status.success() {
-                true
-            } else {
-                println!("Failed to launch valgrind. Error: {}. Please ensure that valgrind is installed and on the $PATH.", status);
-                false
-            }
+        Ok(output) => output,
+    };
+
+    if !output.status.success() {
+        println!("Failed to launch valgrind. Error: {}. Please ensure that valgrind is installed and on the $PATH.", output.status);
+        return false;
+    }
+
+    // Older versions silently ignore the client requests that exclude parts of a benchmark, which
+    // would make the results of those benchmarks include everything they exclude
+    let version = String::from_utf8_lossy(&output.stdout);
+    match parse_valgrind_version(&version) {
+        Some(version) if version >= MIN_VALGRIND_VERSION => true,
+        _ => {
+            println!(
+                "Unsupported valgrind version: {}. Valgrind {}.{} or later is required.",
+                version.trim(),
+                MIN_VALGRIND_VERSION.0,
+                MIN_VALGRIND_VERSION.1
+            );
+            false
         }
     }
 }
 
+/// Parses the major and minor version out of the output of `valgrind --version`, which looks
+/// like `valgrind-3.22.0`
+fn parse_valgrind_version(version: &str) -> Option<(u32, u32)> {
+    let mut parts = version.trim().strip_prefix("valgrind-")?.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts
+        .next()?
+        .split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()?;
+    Some((major, minor))
+}
+
 pub fn run_bench(arch: &str, executable: &str, i: isize, name: &str) -> u64 {
     let output_file = PathBuf::from(format!("target/cachegrind/cachegrind.out.{}", name));
     std::fs::create_dir_all(output_file.parent().unwrap()).expect("Failed to create directory");
@@ -0,0 +1,113 @@
+//! Cachegrind client requests, used to exclude parts of a benchmark from the instruction count
+//!
+//! Cachegrind supports these requests since valgrind 3.22. Older versions ignore them, which means
+//! that everything gets counted, as if the requests were not there, so the runner refuses to use
+//! them. Outside of valgrind, the requests are harmless no-ops.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Base value for cachegrind requests, as defined by `VG_USERREQ_TOOL_BASE('C', 'G')`
+const CACHEGRIND_REQUEST_BASE: usize = ((b'C' as usize) << 24) | ((b'G' as usize) << 16);
+const START_INSTRUMENTATION: usize = CACHEGRIND_REQUEST_BASE;
+const STOP_INSTRUMENTATION: usize = CACHEGRIND_REQUEST_BASE + 1;
+
+/// Whether instructions are being counted, which is the case when a benchmark starts
+static RUNNING: AtomicBool = AtomicBool::new(true);
+
+/// Resume counting instructions, after a call to [`stop_instrumentation`]
+pub fn start_instrumentation() {
+    // Updating the allocation counter state outside of the instrumented region keeps it out of
+    // the instruction count
+    super::alloc::set_counting(true);
+    RUNNING.store(true, Ordering::Relaxed);
+    client_request(START_INSTRUMENTATION);
+}
+
+/// Stop counting instructions until [`start_instrumentation`] is called
+///
+/// Allocations are not counted either while instrumentation is stopped.
+///
+/// Benchmarks that stop instrumentation must start it again before returning, so the code that
+/// runs after the benchmark is counted just like in the calibration run.
+pub fn stop_instrumentation() {
+    client_request(STOP_INSTRUMENTATION);
+    RUNNING.store(false, Ordering::Relaxed);
+    super::alloc::set_counting(false);
+}
+
+/// Runs the provided closure, counting instructions only if `measure` is true
+///
+/// Must be called while instrumentation is stopped.
+pub fn measure_if<T>(measure: bool, f: impl FnOnce() -> T) -> T {
+    if measure {
+        start_instrumentation();
+    }
+
+    let result = f();
+
+    if measure {
+        stop_instrumentation();
+    }
+
+    result
+}
+
+/// Runs the provided closure without counting its instructions and allocations, leaving
+/// instrumentation in the state it was in
+///
+/// Unlike [`measure_if`], this may be called whether instrumentation is running or not.
+pub(super) fn exclude<T>(f: impl FnOnce() -> T) -> T {
+    let running = RUNNING.load(Ordering::Relaxed);
+    if running {
+        stop_instrumentation();
+    }
+
+    let result = f();
+
+    if running {
+        start_instrumentation();
+    }
+
+    result
+}
+
+/// Issues a client request without arguments (see `VALGRIND_DO_CLIENT_REQUEST` in `valgrind.h`)
+#[cfg(target_arch = "x86_64")]
+fn client_request(request: usize) {
+    let args = [request, 0, 0, 0, 0, 0];
+    unsafe {
+        // The rotations add up to 128 bits, so `rdi` is left untouched
+        std::arch::asm!(
+            "rol rdi, 3",
+            "rol rdi, 13",
+            "rol rdi, 61",
+            "rol rdi, 51",
+            "xchg rbx, rbx",
+            inout("rdx") 0usize => _,
+            in("rax") args.as_ptr(),
+            options(nostack),
+        );
+    }
+}
+
+/// Issues a client request without arguments (see `VALGRIND_DO_CLIENT_REQUEST` in `valgrind.h`)
+#[cfg(target_arch = "aarch64")]
+fn client_request(request: usize) {
+    let args = [request, 0, 0, 0, 0, 0];
+    unsafe {
+        // The rotations add up to 128 bits, so `x12` is left untouched
+        std::arch::asm!(
+            "ror x12, x12, #3",
+            "ror x12, x12, #13",
+            "ror x12, x12, #51",
+            "ror x12, x12, #61",
+            "orr x10, x10, x10",
+            inout("x3") 0usize => _,
+            in("x4") args.as_ptr(),
+            options(nostack),
+        );
+    }
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn client_request(_request: usize) {}
@@ -0,0 +1,89 @@
+//! Benchmark-specific counters, such as the number of round trips needed by a transfer
+//!
+//! Benchmarks record metrics while they run, and the top-level run reports them next to the
+//! instruction counts. Like instruction counts, the metrics recorded by a benchmark's setup are
+//! subtracted. Metrics that the setup accounts for entirely are left out of the report.
+//!
+//! Recording a metric takes a lock and may allocate, so it is excluded from the instruction and
+//! allocation counts of the benchmark.
+
+use super::instrumentation;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+static METRICS: Mutex<Vec<(&'static str, u64)>> = Mutex::new(Vec::new());
+
+/// A named value recorded by a benchmark
+#[derive(Clone)]
+pub struct Metric {
+    pub name: String,
+    pub value: u64,
+}
+
+/// Records a metric for the running benchmark, adding to the value recorded so far under the same
+/// name
+pub fn record_metric(name: &'static str, value: u64) {
+    instrumentation::exclude(|| {
+        let mut metrics = METRICS.lock().unwrap();
+        match metrics.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, total)) => *total += value,
+            None => metrics.push((name, value)),
+        }
+    });
+}
+
+/// Subtracts the metrics recorded by a benchmark's setup from those of the benchmark
+pub fn subtract(metrics: &[Metric], setup: &[Metric]) -> Vec<Metric> {
+    metrics
+        .iter()
+        .filter_map(|metric| {
+            let Some(setup_metric) = setup.iter().find(|m| m.name == metric.name) else {
+                return Some(metric.clone());
+            };
+
+            let value = metric.value.saturating_sub(setup_metric.value);
+            (value > 0).then(|| Metric {
+                name: metric.name.clone(),
+                value,
+            })
+        })
+        .collect()
+}
+
+/// Returns the path of the file holding the metrics of the given benchmark
+pub fn metrics_path(name: &str) -> PathBuf {
+    PathBuf::from(format!("target/cachegrind/metrics.out.{}", name))
+}
+
+/// Writes the metrics recorded so far, one per line, if any
+pub fn write(path: &Path) {
+    let metrics = METRICS.lock().unwrap();
+    if metrics.is_empty() {
+        return;
+    }
+
+    let contents: String = metrics
+        .iter()
+        .map(|(name, value)| format!("{} {}\n", value, name))
+        .collect();
+    fs::write(path, contents).expect("Unable to write metrics");
+}
+
+/// Reads the metrics written by a benchmark process, which did not record any if there is no file
+pub fn read(path: &Path) -> Vec<Metric> {
+    let Ok(contents) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+
+    contents
+        .lines()
+        .map(|line| {
+            let (value, name) = line.split_once(' ').expect("Invalid metric");
+            Metric {
+                name: name.to_owned(),
+                value: value.parse().expect("Invalid metric"),
+            }
+        })
+        .collect()
+}
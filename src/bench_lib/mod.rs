@@ -1,8 +1,14 @@
+mod alloc;
 pub mod benchmark;
 mod cachegrind;
+mod instrumentation;
+mod metrics;
 mod runner;
 
+pub use alloc::{enabled as allocations_counted, retain};
 pub use benchmark::Benchmark;
+pub use instrumentation::{measure_if, start_instrumentation, stop_instrumentation};
+pub use metrics::record_metric;
 pub use runner::main;
 
 pub fn black_box<T>(dummy: T) -> T {
@@ -1,5 +1,8 @@
+use super::alloc::{self, AllocStats};
 use super::benchmark::{self, Benchmark, ReportingMode};
 use super::cachegrind;
+use super::instrumentation;
+use super::metrics::{self, Metric};
 use rayon::prelude::*;
 use std::collections::HashMap;
 
@@ -24,12 +27,21 @@ fn run_single(index: &str, benchmarks: &[Benchmark]) {
 
     // -1 is used as a signal to do nothing and return. By recording an empty benchmark, we can
     // subtract out the overhead from startup and dispatching to the right benchmark.
-    if index == -1 {
-        return;
+    let name = if index == -1 {
+        "calibration"
+    } else {
+        let bench = &benchmarks[index as usize];
+        bench.run();
+        bench.name()
+    };
+
+    if alloc::enabled() {
+        AllocStats::current().write(&alloc::stats_path(name));
     }
 
-    let index = index as usize;
-    benchmarks[index].run();
+    // Writing metrics is bookkeeping rather than benchmark work, so it is left out of the count
+    instrumentation::stop_instrumentation();
+    metrics::write(&metrics::metrics_path(name));
 }
 
 /// Run all the provided benches under cachegrind to retrieve their instruction count
@@ -42,6 +54,7 @@ fn run_all(executable: &str, benches: &[Benchmark]) {
 
     let arch = cachegrind::get_arch();
     let calibration = cachegrind::run_bench(&arch, executable, -1, "calibration");
+    let calibration_allocs = read_alloc_stats("calibration");
 
     let results: HashMap<_, _> = benches
         .par_iter()
@@ -49,18 +62,102 @@ fn run_all(executable: &str, benches: &[Benchmark]) {
         .map(|(i, bench)| {
             let instr_count =
                 cachegrind::run_bench(&arch, &executable, i as isize, bench.name()) - calibration;
-            (bench.name(), instr_count)
+            let allocs = read_alloc_stats(bench.name())
+                .map(|allocs| allocs.subtract(&calibration_allocs.unwrap()));
+            (
+                bench.name(),
+                (instr_count, allocs, read_metrics(bench.name())),
+            )
         })
         .collect();
 
     for bench in benches {
-        let instr_count = match bench.reporting_mode() {
+        let (instr_count, allocs, metrics) = match bench.reporting_mode() {
             ReportingMode::Hidden => continue,
-            ReportingMode::AllInstructions => results[bench.name()],
+            ReportingMode::AllInstructions => {
+                let (instr_count, allocs, metrics) = &results[bench.name()];
+                (*instr_count, *allocs, metrics.clone())
+            }
             ReportingMode::AllInstructionsExceptSetup(setup_name) => {
-                results[bench.name()] - results[setup_name.as_str()]
+                let (instr_count, allocs, metrics) = &results[bench.name()];
+                let (setup_instr_count, setup_allocs, setup_metrics) =
+                    &results[setup_name.as_str()];
+                let allocs = allocs.map(|allocs| allocs.subtract(&setup_allocs.unwrap()));
+                (
+                    instr_count - setup_instr_count,
+                    allocs,
+                    metrics::subtract(metrics, setup_metrics),
+                )
             }
         };
-        println!("{} : {}", instr_count, bench.name());
+
+        let mut details: Vec<_> = bench
+            .work_units()
+            .iter()
+            .map(|unit| {
+                format!(
+                    "{:.2} per {}",
+                    instr_count as f64 / unit.count as f64,
+                    unit.name
+                )
+            })
+            .collect();
+        details.extend(
+            metrics
+                .iter()
+                .map(|metric| format!("{}: {}", metric.name, metric.value)),
+        );
+        if let Some(allocs) = allocs {
+            details.push(format!(
+                "allocs: {}, deallocs: {}, reallocs: {}, peak bytes: {}, retained bytes: {}",
+                allocs.allocations,
+                allocs.deallocations,
+                allocs.reallocations,
+                allocs.peak_bytes,
+                allocs.retained_bytes
+            ));
+
+            // Benchmarks that keep objects alive report how much memory each unit occupies
+            if allocs.retained_bytes > 0 {
+                details.extend(bench.work_units().iter().map(|unit| {
+                    format!(
+                        "{:.2} retained bytes per {}",
+                        allocs.retained_bytes as f64 / unit.count as f64,
+                        unit.name
+                    )
+                }));
+            }
+        }
+
+        if details.is_empty() {
+            println!("{} : {}", instr_count, bench.name());
+        } else {
+            println!(
+                "{} : {} ({})",
+                instr_count,
+                bench.name(),
+                details.join(", ")
+            );
+        }
+    }
+}
+
+/// Reads the allocation statistics written by a benchmark process, if allocations are counted
+fn read_alloc_stats(name: &str) -> Option<AllocStats> {
+    if !alloc::enabled() {
+        return None;
     }
+
+    let path = alloc::stats_path(name);
+    let stats = AllocStats::read(&path);
+    std::fs::remove_file(path).ok();
+    Some(stats)
+}
+
+/// Reads the metrics recorded by a benchmark process
+fn read_metrics(name: &str) -> Vec<Metric> {
+    let path = metrics::metrics_path(name);
+    let metrics = metrics::read(&path);
+    std::fs::remove_file(path).ok();
+    metrics
 }
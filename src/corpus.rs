@@ -0,0 +1,91 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Number of evenly spaced positions at which a byte of the original record is flipped
+const BIT_FLIP_POSITIONS: usize = 64;
+
+/// Number of inputs consisting of pseudo-random bytes
+const RANDOM_INPUTS: usize = 16;
+
+/// Size of the inputs consisting of pseudo-random bytes
+const RANDOM_INPUT_SIZE: usize = 512;
+
+/// Derives malformed variants of a valid TLS record, which must include its 5-byte header
+///
+/// The mutations are deterministic, so a corpus generated from the same record is always the same.
+/// Each input is returned with a name describing the mutation, prefixed with `prefix`. The
+/// original record is included as well, as a baseline.
+pub fn mutations(prefix: &str, record: &[u8]) -> Vec<(String, Vec<u8>)> {
+    assert!(record.len() > 5, "record is too short to mutate");
+    let mut inputs = vec![(format!("{prefix}_original"), record.to_vec())];
+
+    for len in [1, 5, record.len() / 2, record.len() - 1] {
+        inputs.push((format!("{prefix}_truncated_{len}"), record[..len].to_vec()));
+    }
+
+    let step = record.len().div_ceil(BIT_FLIP_POSITIONS);
+    for pos in (0..record.len()).step_by(step) {
+        let mut input = record.to_vec();
+        input[pos] ^= 0x80;
+        inputs.push((format!("{prefix}_flipped_{pos}"), input));
+    }
+
+    for (name, content_type) in [("invalid_content_type", 0x99), ("alert_content_type", 0x15)] {
+        let mut input = record.to_vec();
+        input[0] = content_type;
+        inputs.push((format!("{prefix}_{name}"), input));
+    }
+
+    for (name, record_len) in [("zero_length", 0u16), ("oversized_length", 0xffff)] {
+        let mut input = record.to_vec();
+        input[3..5].copy_from_slice(&record_len.to_be_bytes());
+        inputs.push((format!("{prefix}_{name}"), input));
+    }
+
+    // The handshake message length follows the record header and the handshake type
+    let mut input = record.to_vec();
+    input[6..9].copy_from_slice(&[0xff, 0xff, 0xff]);
+    inputs.push((format!("{prefix}_oversized_handshake_length"), input));
+
+    inputs.push((format!("{prefix}_repeated"), record.repeat(2)));
+
+    inputs
+}
+
+/// Generates inputs consisting of pseudo-random bytes, which are the same on every call
+pub fn random_inputs() -> Vec<(String, Vec<u8>)> {
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    (0..RANDOM_INPUTS)
+        .map(|i| {
+            let input = (0..RANDOM_INPUT_SIZE)
+                .map(|_| {
+                    // xorshift64
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect();
+            (format!("random_{i}"), input)
+        })
+        .collect()
+}
+
+/// Stores each input in its own file in `dir`
+pub fn save(dir: &Path, inputs: &[(String, Vec<u8>)]) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    for (name, input) in inputs {
+        fs::write(dir.join(format!("{name}.bin")), input)?;
+    }
+    Ok(())
+}
+
+/// Lists the inputs in `dir`, sorted by name so the order does not depend on the file system
+pub fn list(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    paths.sort();
+    Ok(paths)
+}
@@ -1,81 +1,56 @@
 use crate::bench_lib::{black_box, Benchmark};
+use crate::transcript::{Direction, Transcript};
+use crate::transport::{complete_handshake, Measured, Transport};
+use std::collections::HashMap;
+use std::fmt;
 use std::fs;
-use std::io::{self, Read, Write};
+use std::io::{self, IoSlice, Read, Write};
 use std::ops::Deref;
 use std::ops::DerefMut;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use rustls::client::Resumption;
+use rustls::client::{ClientSessionMemoryCache, ClientSessionStore, Resumption};
 use rustls::crypto::ring::Ring;
-use rustls::server::{NoServerSessionStorage, ServerSessionMemoryCache, WebPkiClientVerifier};
-use rustls::RootCertStore;
-use rustls::Ticketer;
+use rustls::server::{
+    ClientHello, NoServerSessionStorage, ResolvesServerCert, ResolvesServerCertUsingSni,
+    ServerSessionMemoryCache, StoresServerSessions, WebPkiClientVerifier,
+};
+use rustls::sign::{self, CertifiedKey};
+use rustls::{AlertDescription, CertificateError, NamedGroup, PeerIncompatible, RootCertStore};
 use rustls::{ClientConfig, ClientConnection};
 use rustls::{ConnectionCommon, SideData};
+use rustls::{ProducesTickets, Ticketer};
 use rustls::{ServerConfig, ServerConnection};
+use rustls::{Stream, StreamOwned};
 
 mod bench_lib;
+mod corpus;
+mod transcript;
+mod transport;
 
-fn transfer<L, R, LS, RS>(left: &mut L, right: &mut R, expect_data: Option<usize>)
-where
-    L: DerefMut + Deref<Target = ConnectionCommon<LS>>,
-    R: DerefMut + Deref<Target = ConnectionCommon<RS>>,
-    LS: SideData,
-    RS: SideData,
-{
-    let mut tls_buf = [0u8; 262144];
-    let mut data_left = expect_data;
-    let mut data_buf = [0u8; 8192];
-
-    loop {
-        let mut sz = 0;
+/// Amount of early data sent by the client in 0-RTT benchmarks
+const EARLY_DATA_SIZE: usize = 1024;
 
-        while left.wants_write() {
-            let written = left.write_tls(&mut tls_buf[sz..].as_mut()).unwrap();
-            if written == 0 {
-                break;
-            }
-
-            sz += written;
-        }
+/// Size of the application writes done by the client in socket benchmarks
+const SOCKET_WRITE_SIZE: usize = 16384;
 
-        if sz == 0 {
-            return;
-        }
+/// Size of the dummy OCSP response stapled by the server, similar to that of a real response
+const OCSP_RESPONSE_SIZE: usize = 1500;
 
-        let mut offs = 0;
-        loop {
-            match right.read_tls(&mut tls_buf[offs..sz].as_ref()) {
-                Ok(read) => {
-                    right.process_new_packets().unwrap();
-                    offs += read;
-                }
-                Err(err) => {
-                    panic!("error on transfer {}..{}: {}", offs, sz, err);
-                }
-            }
+/// Number and size of the dummy signed certificate timestamps sent by the server
+const SCT_COUNT: usize = 2;
+const SCT_SIZE: usize = 119;
 
-            if let Some(left) = &mut data_left {
-                loop {
-                    let sz = match right.reader().read(&mut data_buf) {
-                        Ok(sz) => sz,
-                        Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
-                        Err(err) => panic!("failed to read data: {}", err),
-                    };
-
-                    *left -= sz;
-                    if *left == 0 {
-                        break;
-                    }
-                }
-            }
+/// Number of handshakes each thread runs in the wall-clock scenario
+const WALL_CLOCK_HANDSHAKES_PER_THREAD: u32 = 500;
 
-            if sz == offs {
-                break;
-            }
-        }
-    }
-}
+/// Size of the dummy sessions used to fill server session caches, roughly that of a real session
+const FILLER_SESSION_SIZE: usize = 128;
 
 #[derive(PartialEq, Clone, Copy)]
 enum ClientAuth {
@@ -88,6 +63,8 @@ enum ResumptionParam {
     No,
     SessionID,
     Tickets,
+    /// Stateful resumption with 0-RTT early data (TLS 1.3 only)
+    EarlyData,
 }
 
 impl ResumptionParam {
@@ -96,6 +73,36 @@ impl ResumptionParam {
             Self::No => "no_resume",
             Self::SessionID => "session_id",
             Self::Tickets => "tickets",
+            Self::EarlyData => "early_data",
+        }
+    }
+}
+
+/// The buffer limit configured on both sides of a connection
+#[derive(PartialEq, Clone, Copy)]
+enum BufferLimit {
+    /// The limit rustls uses by default
+    Default,
+    /// No limit at all
+    Unlimited,
+    /// A limit of the given amount of bytes
+    Bytes(usize),
+}
+
+impl BufferLimit {
+    fn label(&self) -> String {
+        match *self {
+            Self::Default => "default_limit".to_owned(),
+            Self::Unlimited => "no_limit".to_owned(),
+            Self::Bytes(limit) => format!("limit_{limit}"),
+        }
+    }
+
+    fn apply<S: SideData>(&self, conn: &mut ConnectionCommon<S>) {
+        match *self {
+            Self::Default => {}
+            Self::Unlimited => conn.set_buffer_limit(None),
+            Self::Bytes(limit) => conn.set_buffer_limit(Some(limit)),
         }
     }
 }
@@ -156,6 +163,21 @@ impl KeyType {
         )
     }
 
+    fn get_expired_chain(&self) -> Vec<rustls::Certificate> {
+        rustls_pemfile::certs(&mut io::BufReader::new(
+            fs::File::open(self.path_for("end.expired.fullchain")).unwrap(),
+        ))
+        .unwrap()
+        .iter()
+        .map(|v| rustls::Certificate(v.clone()))
+        .collect()
+    }
+
+    fn get_certified_key(&self) -> CertifiedKey {
+        let key = sign::any_supported_type(&self.get_key()).unwrap();
+        CertifiedKey::new(self.get_chain(), key)
+    }
+
     fn get_client_chain(&self) -> Vec<rustls::Certificate> {
         rustls_pemfile::certs(&mut io::BufReader::new(
             fs::File::open(self.path_for("client.fullchain")).unwrap(),
@@ -166,6 +188,10 @@ impl KeyType {
         .collect()
     }
 
+    fn get_ca_der(&self) -> Vec<u8> {
+        fs::read(self.path_for("ca.der")).unwrap()
+    }
+
     fn get_client_key(&self) -> rustls::PrivateKey {
         rustls::PrivateKey(
             rustls_pemfile::pkcs8_private_keys(&mut io::BufReader::new(
@@ -177,6 +203,31 @@ impl KeyType {
     }
 }
 
+/// Copies of the CA certificate with an index in the common name; their signatures are invalid,
+/// which does not matter since a `RootCertStore` only parses roots
+fn synthetic_roots(params: &BenchmarkParam, count: usize) -> Vec<Vec<u8>> {
+    let ca = params.key_type.get_ca_der();
+    let marker = b"ponytown";
+    let marker_offsets: Vec<_> = ca
+        .windows(marker.len())
+        .enumerate()
+        .filter(|(_, window)| *window == marker)
+        .map(|(offset, _)| offset)
+        .collect();
+    assert!(!marker_offsets.is_empty());
+
+    (0..count)
+        .map(|i| {
+            let mut root = ca.clone();
+            let name = format!("{:08}", i);
+            for &offset in &marker_offsets {
+                root[offset..offset + marker.len()].copy_from_slice(name.as_bytes());
+            }
+            root
+        })
+        .collect()
+}
+
 fn make_server_config(
     params: &BenchmarkParam,
     client_auth: ClientAuth,
@@ -210,6 +261,11 @@ fn make_server_config(
         cfg.session_storage = ServerSessionMemoryCache::new(128);
     } else if resume == ResumptionParam::Tickets {
         cfg.ticketer = Ticketer::new().unwrap();
+    } else if resume == ResumptionParam::EarlyData {
+        // rustls only accepts early data with stateful resumption, because taking the session out
+        // of the cache is what protects against replays
+        cfg.session_storage = ServerSessionMemoryCache::new(128);
+        cfg.max_early_data_size = EARLY_DATA_SIZE as u32;
     } else {
         cfg.session_storage = Arc::new(NoServerSessionStorage {});
     }
@@ -222,12 +278,34 @@ fn make_client_config(
     params: &BenchmarkParam,
     clientauth: ClientAuth,
     resume: ResumptionParam,
+) -> ClientConfig<Ring> {
+    make_client_config_with_roots(params, clientauth, resume, &[])
+}
+
+/// The real CA goes last, so the trust anchor lookup has to skip all the synthetic roots
+fn make_client_config_with_roots(
+    params: &BenchmarkParam,
+    clientauth: ClientAuth,
+    resume: ResumptionParam,
+    synthetic_roots: &[Vec<u8>],
 ) -> ClientConfig<Ring> {
     let mut root_store = RootCertStore::empty();
+    let (_, ignored) = root_store.add_parsable_certificates(synthetic_roots);
+    assert_eq!(ignored, 0);
+
     let mut rootbuf =
         io::BufReader::new(fs::File::open(params.key_type.path_for("ca.cert")).unwrap());
     root_store.add_parsable_certificates(&rustls_pemfile::certs(&mut rootbuf).unwrap());
 
+    make_client_config_with_root_store(params, clientauth, resume, root_store)
+}
+
+fn make_client_config_with_root_store(
+    params: &BenchmarkParam,
+    clientauth: ClientAuth,
+    resume: ResumptionParam,
+    root_store: RootCertStore,
+) -> ClientConfig<Ring> {
     let cfg = ClientConfig::builder()
         .with_cipher_suites(&[params.ciphersuite])
         .with_safe_default_kx_groups()
@@ -251,6 +329,8 @@ fn make_client_config(
         cfg.resumption = Resumption::disabled();
     }
 
+    cfg.enable_early_data = resume == ResumptionParam::EarlyData;
+
     cfg
 }
 
@@ -264,129 +344,2164 @@ fn new_connection(
 
     assert!(params.ciphersuite.version() == params.version);
 
+    connect(client_config, server_config)
+}
+
+fn connect(
+    client_config: Arc<ClientConfig<Ring>>,
+    server_config: Arc<ServerConfig<Ring>>,
+) -> (ClientConnection, ServerConnection) {
     let server_name = "localhost".try_into().unwrap();
-    let client = ClientConnection::new(Arc::clone(&client_config), server_name).unwrap();
-    let server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    let client = ClientConnection::new(client_config, server_name).unwrap();
+    let server = ServerConnection::new(server_config).unwrap();
     (client, server)
 }
 
+fn new_connection_with_roots(
+    params: &BenchmarkParam,
+    root_count: usize,
+) -> (ClientConnection, ServerConnection) {
+    let synthetic_roots = synthetic_roots(params, root_count);
+    let client_config = Arc::new(make_client_config_with_roots(
+        params,
+        ClientAuth::No,
+        ResumptionParam::No,
+        &synthetic_roots,
+    ));
+    let server_config = Arc::new(make_server_config(
+        params,
+        ClientAuth::No,
+        ResumptionParam::No,
+        None,
+    ));
+    connect(client_config, server_config)
+}
+
 fn bench_new_connection(params: &BenchmarkParam, clientauth: ClientAuth, resume: ResumptionParam) {
-    black_box(new_connection(params, clientauth, resume));
+    black_box((new_connection(params, clientauth, resume), Transport::new()));
 }
 
 fn bench_handshake(params: &BenchmarkParam, clientauth: ClientAuth, resume: ResumptionParam) {
     let (mut client, mut server) = new_connection(params, clientauth, resume);
+    do_handshake(&mut Transport::new(), &mut client, &mut server);
+}
 
-    transfer(&mut client, &mut server, None);
-    transfer(&mut server, &mut client, None);
-    transfer(&mut client, &mut server, None);
-    transfer(&mut server, &mut client, None);
+/// Returns the hidden `new_conn_{name}` setup and the `handshake_{name}` benchmark for connections
+/// created by `setup`, which `check` inspects after the handshake
+fn handshake_benchmarks(
+    name: String,
+    get_param: fn() -> BenchmarkParam,
+    setup: impl Fn(&BenchmarkParam) -> (ClientConnection, ServerConnection)
+        + Copy
+        + Send
+        + Sync
+        + 'static,
+    check: impl Fn(&BenchmarkParam, &ClientConnection, &ServerConnection) + Send + Sync + 'static,
+) -> [Benchmark; 2] {
+    [
+        Benchmark::new(format!("new_conn_{name}"), move || {
+            black_box((setup(&black_box(get_param())), Transport::new()));
+        })
+        .hidden(),
+        Benchmark::new(format!("handshake_{name}"), move || {
+            let params = black_box(get_param());
+            let (mut client, mut server) = setup(&params);
+            do_handshake(&mut Transport::new(), &mut client, &mut server);
+            check(&params, &client, &server);
+        })
+        .exclude_setup_instructions(format!("new_conn_{name}")),
+    ]
 }
 
-fn do_handshake(client: &mut ClientConnection, server: &mut ServerConnection) {
-    loop {
-        transfer(client, server, None);
-        transfer(server, client, None);
-        if !server.is_handshaking() && !client.is_handshaking() {
-            break;
-        }
-    }
+fn bench_synthetic_roots(params: &BenchmarkParam, root_count: usize) {
+    black_box(synthetic_roots(params, root_count));
 }
 
-fn bench_transfer(params: &BenchmarkParam, plaintext_size: u64, max_fragment_size: Option<usize>) {
-    let client_config = Arc::new(make_client_config(
+fn bench_client_config_with_roots(params: &BenchmarkParam, root_count: usize) {
+    let synthetic_roots = synthetic_roots(params, root_count);
+    black_box(make_client_config_with_roots(
         params,
         ClientAuth::No,
         ResumptionParam::No,
+        &synthetic_roots,
+    ));
+}
+
+/// Runs a full handshake, so the client config holds a ticket to send early data with
+fn primed_early_data_configs(
+    params: &BenchmarkParam,
+) -> (Arc<ClientConfig<Ring>>, Arc<ServerConfig<Ring>>) {
+    let client_config = Arc::new(make_client_config(
+        params,
+        ClientAuth::No,
+        ResumptionParam::EarlyData,
     ));
     let server_config = Arc::new(make_server_config(
         params,
         ClientAuth::No,
-        ResumptionParam::No,
-        max_fragment_size,
+        ResumptionParam::EarlyData,
+        None,
     ));
 
-    let server_name = "localhost".try_into().unwrap();
-    let mut client = ClientConnection::new(client_config, server_name).unwrap();
-    client.set_buffer_limit(None);
-    let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-    server.set_buffer_limit(None);
+    let (mut client, mut server) = connect(Arc::clone(&client_config), Arc::clone(&server_config));
+    do_handshake(&mut Transport::new(), &mut client, &mut server);
 
-    do_handshake(&mut client, &mut server);
+    (client_config, server_config)
+}
 
-    let mut buf = Vec::new();
-    buf.resize(plaintext_size as usize, 0u8);
+fn bench_primed_early_data(params: &BenchmarkParam) {
+    black_box((primed_early_data_configs(params), Transport::new()));
+}
 
-    server.writer().write_all(&buf).unwrap();
-    transfer(&mut server, &mut client, Some(buf.len()));
+fn bench_handshake_early_data(params: &BenchmarkParam) {
+    let (client_config, server_config) = primed_early_data_configs(params);
+
+    let (mut client, mut server) = connect(client_config, server_config);
+
+    let mut transport = Transport::new();
+
+    let early_data = [0u8; EARLY_DATA_SIZE];
+    client
+        .early_data()
+        .expect("client has no ticket to send early data with")
+        .write_all(&early_data)
+        .unwrap();
+
+    do_handshake(&mut transport, &mut client, &mut server);
+    assert!(client.is_early_data_accepted());
+
+    let mut received = Vec::new();
+    server
+        .early_data()
+        .expect("server did not accept early data")
+        .read_to_end(&mut received)
+        .unwrap();
+    assert_eq!(received.len(), EARLY_DATA_SIZE);
 }
 
-fn add_benchmarks_for_params(benchmarks: &mut Vec<Benchmark>, get_param: fn() -> BenchmarkParam) {
-    let tls = format!("{:?}", get_param().version);
-    let all_resumption_params = [
-        ResumptionParam::No,
-        ResumptionParam::SessionID,
-        ResumptionParam::Tickets,
-    ];
+/// With resumption, every handshake after the first resumes the session of the previous one
+fn bench_shared_configs(params: &BenchmarkParam, resume: ResumptionParam, connections: u64) {
+    let client_config = Arc::new(make_client_config(params, ClientAuth::No, resume));
+    let server_config = Arc::new(make_server_config(params, ClientAuth::No, resume, None));
+    let mut transport = Transport::new();
+    let mut traffic = HandshakeTraffic::default();
 
-    // Benchmark handshake with and without resumption
-    for resumption_param in all_resumption_params {
-        benchmarks.extend([
-            Benchmark::new(
-                format!("new_conn_{}_{tls}", resumption_param.label()),
-                move || {
-                    bench_new_connection(
-                        &black_box(get_param()),
-                        black_box(ClientAuth::No),
-                        black_box(ResumptionParam::No),
-                    )
-                },
-            )
-            .hidden(),
-            Benchmark::new(
-                format!("handshake_{}_{tls}", resumption_param.label()),
-                move || {
-                    bench_handshake(
-                        &black_box(get_param()),
-                        black_box(ClientAuth::No),
-                        black_box(ResumptionParam::No),
-                    )
-                },
-            )
-            .exclude_setup_instructions(format!("new_conn_{}_{tls}", resumption_param.label())),
-        ])
+    for _ in 0..connections {
+        let (mut client, mut server) =
+            connect(Arc::clone(&client_config), Arc::clone(&server_config));
+        traffic.add(&run_handshake(&mut transport, &mut client, &mut server));
     }
 
-    // Benchmark data transfer
-    benchmarks.extend([
-        Benchmark::new(format!("transfer_no_resume_{tls}"), move || {
-            bench_transfer(
-                &black_box(get_param()),
-                black_box(1024 * 1024),
-                black_box(None),
-            )
-        })
-        .exclude_setup_instructions(format!("handshake_no_resume_{tls}")),
-    ]);
+    if connections > 0 {
+        traffic.record_totals();
+    }
 }
 
-fn main() {
-    let params = [
-        || {
-            BenchmarkParam::new(
-                KeyType::Rsa,
-                rustls::cipher_suite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
-                &rustls::version::TLS12,
-            )
-        },
-        || {
-            BenchmarkParam::new(
-                KeyType::Rsa,
-                rustls::cipher_suite::TLS13_AES_128_GCM_SHA256,
-                &rustls::version::TLS13,
-            )
-        },
-    ];
+/// The server only supports the last protocol offered by the client
+fn alpn_connection(
+    params: &BenchmarkParam,
+    protocol_count: usize,
+) -> (ClientConnection, ServerConnection) {
+    let protocols = |prefix: &str| -> Vec<Vec<u8>> {
+        (1..protocol_count)
+            .map(|i| format!("{prefix}-{i}").into_bytes())
+            .chain([b"h2".to_vec()])
+            .collect()
+    };
+
+    let mut client_config = make_client_config(params, ClientAuth::No, ResumptionParam::No);
+    client_config.alpn_protocols = protocols("client-only");
+    let mut server_config = make_server_config(params, ClientAuth::No, ResumptionParam::No, None);
+    server_config.alpn_protocols = protocols("server-only");
+    connect(Arc::new(client_config), Arc::new(server_config))
+}
+
+/// Shares one certificate between all names, which `ResolvesServerCertUsingSni` refuses to do, so
+/// the `handshake_sni_*` results measure this `HashMap` lookup rather than rustls' resolver
+struct SniResolver {
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl SniResolver {
+    fn new(params: &BenchmarkParam, name_count: usize) -> Self {
+        let certified_key = Arc::new(params.key_type.get_certified_key());
+
+        let by_name = (1..name_count)
+            .map(|i| format!("host{i}.example.com"))
+            .chain(["localhost".to_owned()])
+            .map(|name| (name, Arc::clone(&certified_key)))
+            .collect();
+        Self { by_name }
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        self.by_name.get(client_hello.server_name()?).cloned()
+    }
+}
+
+impl fmt::Debug for SniResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SniResolver")
+            .field("names", &self.by_name.len())
+            .finish_non_exhaustive()
+    }
+}
+
+fn sni_connection(
+    params: &BenchmarkParam,
+    name_count: usize,
+) -> (ClientConnection, ServerConnection) {
+    let client_config = make_client_config(params, ClientAuth::No, ResumptionParam::No);
+    let mut server_config = make_server_config(params, ClientAuth::No, ResumptionParam::No, None);
+    server_config.cert_resolver = Arc::new(SniResolver::new(params, name_count));
+    connect(Arc::new(client_config), Arc::new(server_config))
+}
+
+/// Uses the preferred certificate if the client accepts a signature by its key
+struct KeyTypeResolver {
+    preferred: Arc<CertifiedKey>,
+    fallback: Arc<CertifiedKey>,
+}
+
+impl ResolvesServerCert for KeyTypeResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let offered = client_hello.signature_schemes();
+        [&self.preferred, &self.fallback]
+            .into_iter()
+            .find(|certified_key| certified_key.key.choose_scheme(offered).is_some())
+            .cloned()
+    }
+}
+
+impl fmt::Debug for KeyTypeResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyTypeResolver").finish_non_exhaustive()
+    }
+}
+
+#[derive(PartialEq, Clone, Copy)]
+enum CertResolver {
+    Sni,
+    /// With TLS 1.2, the server only sees the signature schemes of the offered cipher suite, so
+    /// preferring the other key type takes the fallback
+    KeyType(KeyType),
+}
+
+impl CertResolver {
+    fn label(&self) -> String {
+        match *self {
+            Self::Sni => "sni".to_owned(),
+            Self::KeyType(key_type) => format!("prefer_{key_type:?}").to_lowercase(),
+        }
+    }
+
+    fn build(&self, params: &BenchmarkParam) -> Arc<dyn ResolvesServerCert> {
+        match *self {
+            Self::Sni => {
+                let mut resolver = ResolvesServerCertUsingSni::new();
+                for name in ["testserver.com", "second.testserver.com", "localhost"] {
+                    resolver
+                        .add(name, params.key_type.get_certified_key())
+                        .unwrap();
+                }
+                Arc::new(resolver)
+            }
+            Self::KeyType(preferred) => {
+                let fallback = match preferred {
+                    KeyType::Rsa => KeyType::Ecdsa,
+                    _ => KeyType::Rsa,
+                };
+                Arc::new(KeyTypeResolver {
+                    preferred: Arc::new(preferred.get_certified_key()),
+                    fallback: Arc::new(fallback.get_certified_key()),
+                })
+            }
+        }
+    }
+
+    fn expected_key_type(&self, params: &BenchmarkParam) -> KeyType {
+        match *self {
+            Self::Sni => params.key_type,
+            Self::KeyType(_) if params.version == &rustls::version::TLS12 => params.key_type,
+            Self::KeyType(preferred) => preferred,
+        }
+    }
+}
+
+/// The client trusts both the RSA and the ECDSA CA
+fn resolver_connection(
+    params: &BenchmarkParam,
+    resolver: CertResolver,
+) -> (ClientConnection, ServerConnection) {
+    let roots = [KeyType::Rsa.get_ca_der(), KeyType::Ecdsa.get_ca_der()];
+    let client_config =
+        make_client_config_with_roots(params, ClientAuth::No, ResumptionParam::No, &roots);
+    let mut server_config = make_server_config(params, ClientAuth::No, ResumptionParam::No, None);
+    server_config.cert_resolver = resolver.build(params);
+    connect(Arc::new(client_config), Arc::new(server_config))
+}
+
+#[derive(PartialEq, Clone, Copy)]
+enum Stapling {
+    Ocsp,
+    Sct,
+    OcspAndSct,
+}
+
+impl Stapling {
+    fn label(&self) -> &'static str {
+        match *self {
+            Self::Ocsp => "ocsp",
+            Self::Sct => "sct",
+            Self::OcspAndSct => "ocsp_sct",
+        }
+    }
+
+    fn ocsp_response(&self) -> Vec<u8> {
+        match *self {
+            Self::Ocsp | Self::OcspAndSct => vec![0x30; OCSP_RESPONSE_SIZE],
+            Self::Sct => Vec::new(),
+        }
+    }
+
+    fn sct_list(&self) -> Vec<u8> {
+        if *self == Self::Ocsp {
+            return Vec::new();
+        }
+
+        let mut scts = Vec::new();
+        for _ in 0..SCT_COUNT {
+            scts.extend_from_slice(&(SCT_SIZE as u16).to_be_bytes());
+            scts.extend_from_slice(&[0; SCT_SIZE]);
+        }
+
+        let mut list = (scts.len() as u16).to_be_bytes().to_vec();
+        list.extend_from_slice(&scts);
+        list
+    }
+}
+
+/// The client does not check the stapled data, so it is dummy data of a realistic size
+fn stapled_connection(
+    params: &BenchmarkParam,
+    stapling: Stapling,
+) -> (ClientConnection, ServerConnection) {
+    let client_config = make_client_config(params, ClientAuth::No, ResumptionParam::No);
+    let mut server_config: ServerConfig<Ring> = ServerConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[params.version])
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert_with_ocsp_and_sct(
+            params.key_type.get_chain(),
+            params.key_type.get_key(),
+            stapling.ocsp_response(),
+            stapling.sct_list(),
+        )
+        .unwrap();
+    // Like `make_server_config`, so only the stapled data differs from `handshake_no_resume`
+    server_config.session_storage = Arc::new(NoServerSessionStorage {});
+    connect(Arc::new(client_config), Arc::new(server_config))
+}
+
+/// A reason for a handshake to fail
+#[derive(PartialEq, Clone, Copy)]
+enum HandshakeFailure {
+    /// The client does not trust the CA that issued the server's certificate
+    UnknownCa,
+    /// The server's certificate is not valid for the name the client connects to
+    WrongServerName,
+    /// The server's certificate has expired
+    ExpiredCertificate,
+    /// The server supports none of the cipher suites offered by the client
+    NoSharedCipherSuite,
+    /// The server supports none of the protocol versions offered by the client
+    NoSharedVersion,
+}
+
+impl HandshakeFailure {
+    fn label(&self) -> &'static str {
+        match *self {
+            Self::UnknownCa => "unknown_ca",
+            Self::WrongServerName => "wrong_server_name",
+            Self::ExpiredCertificate => "expired_cert",
+            Self::NoSharedCipherSuite => "no_shared_cipher_suite",
+            Self::NoSharedVersion => "no_shared_version",
+        }
+    }
+
+    /// Returns the errors the client and the server are expected to report
+    fn expected_errors(&self) -> (rustls::Error, rustls::Error) {
+        use rustls::Error;
+
+        match *self {
+            Self::UnknownCa => (
+                Error::InvalidCertificate(CertificateError::UnknownIssuer),
+                Error::AlertReceived(AlertDescription::UnknownCA),
+            ),
+            Self::WrongServerName => (
+                Error::InvalidCertificate(CertificateError::NotValidForName),
+                Error::AlertReceived(AlertDescription::BadCertificate),
+            ),
+            Self::ExpiredCertificate => (
+                Error::InvalidCertificate(CertificateError::Expired),
+                Error::AlertReceived(AlertDescription::CertificateExpired),
+            ),
+            Self::NoSharedCipherSuite => (
+                Error::AlertReceived(AlertDescription::HandshakeFailure),
+                Error::PeerIncompatible(PeerIncompatible::NoCipherSuitesInCommon),
+            ),
+            Self::NoSharedVersion => (
+                Error::AlertReceived(AlertDescription::ProtocolVersion),
+                Error::PeerIncompatible(PeerIncompatible::Tls12NotOfferedOrEnabled),
+            ),
+        }
+    }
+}
+
+/// Creates connections whose handshake fails for the provided reason
+fn failing_connection(
+    params: &BenchmarkParam,
+    failure: HandshakeFailure,
+) -> (ClientConnection, ServerConnection) {
+    let client_config = match failure {
+        HandshakeFailure::UnknownCa => {
+            let mut root_store = RootCertStore::empty();
+            root_store.add_parsable_certificates(&synthetic_roots(params, 1));
+            make_client_config_with_root_store(
+                params,
+                ClientAuth::No,
+                ResumptionParam::No,
+                root_store,
+            )
+        }
+        _ => make_client_config(params, ClientAuth::No, ResumptionParam::No),
+    };
+
+    let server_config = match failure {
+        HandshakeFailure::ExpiredCertificate => ServerConfig::builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[params.version])
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                params.key_type.get_expired_chain(),
+                params.key_type.get_key(),
+            )
+            .unwrap(),
+        HandshakeFailure::NoSharedCipherSuite => {
+            let other_suite = if params.version == &rustls::version::TLS13 {
+                rustls::cipher_suite::TLS13_AES_256_GCM_SHA384
+            } else {
+                rustls::cipher_suite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384
+            };
+            assert!(other_suite != params.ciphersuite);
+
+            ServerConfig::builder()
+                .with_cipher_suites(&[other_suite])
+                .with_safe_default_kx_groups()
+                .with_protocol_versions(&[params.version])
+                .unwrap()
+                .with_no_client_auth()
+                .with_single_cert(params.key_type.get_chain(), params.key_type.get_key())
+                .unwrap()
+        }
+        HandshakeFailure::NoSharedVersion => {
+            let other_version = if params.version == &rustls::version::TLS13 {
+                &rustls::version::TLS12
+            } else {
+                &rustls::version::TLS13
+            };
+
+            ServerConfig::builder()
+                .with_safe_default_cipher_suites()
+                .with_safe_default_kx_groups()
+                .with_protocol_versions(&[other_version])
+                .unwrap()
+                .with_no_client_auth()
+                .with_single_cert(params.key_type.get_chain(), params.key_type.get_key())
+                .unwrap()
+        }
+        _ => make_server_config(params, ClientAuth::No, ResumptionParam::No, None),
+    };
+
+    let server_name = match failure {
+        HandshakeFailure::WrongServerName => "wrong.example.com",
+        _ => "localhost",
+    };
+
+    let client =
+        ClientConnection::new(Arc::new(client_config), server_name.try_into().unwrap()).unwrap();
+    let server = ServerConnection::new(Arc::new(server_config)).unwrap();
+    (client, server)
+}
+
+/// Only counts the instructions spent by the server
+fn bench_handshake_failure(params: &BenchmarkParam, failure: HandshakeFailure) {
+    bench_lib::stop_instrumentation();
+
+    let (mut client, mut server) = failing_connection(params, failure);
+    let mut transport = Transport::new();
+    let mut traffic = HandshakeTraffic::default();
+    let mut client_error = None;
+    let mut server_error = None;
+
+    while server_error.is_none() || client_error.is_none() {
+        let mut sent = 0;
+
+        if server_error.is_none() {
+            let (client_sent, result) =
+                transport.try_transfer_measured(&mut client, &mut server, Measured::Right);
+            traffic.client_sent(client_sent);
+            sent += client_sent;
+            server_error = result.err();
+        }
+
+        if client_error.is_none() {
+            let client_handshaking = client.is_handshaking();
+            let (server_sent, result) =
+                transport.try_transfer_measured(&mut server, &mut client, Measured::Left);
+            traffic.server_sent(server_sent, client_handshaking);
+            sent += server_sent;
+            client_error = result.err();
+        }
+
+        if sent == 0 {
+            break;
+        }
+    }
+
+    let (expected_client_error, expected_server_error) = failure.expected_errors();
+    assert_eq!(client_error, Some(expected_client_error));
+    assert_eq!(server_error, Some(expected_server_error));
+    traffic.record();
+
+    drop(client);
+    drop(server);
+    drop(transport);
+    bench_lib::start_instrumentation();
+}
+
+/// How much room is left in a session cache after filling it
+#[derive(PartialEq, Clone, Copy)]
+enum CacheFill {
+    /// The cache can hold as many sessions again, so storing a session evicts nothing
+    Spare,
+    /// The cache is at capacity, so storing a session evicts the oldest one
+    Full,
+}
+
+impl CacheFill {
+    fn label(&self) -> &'static str {
+        match *self {
+            Self::Spare => "spare",
+            Self::Full => "full",
+        }
+    }
+}
+
+/// Creates a server session cache of the provided capacity, holding `entries` dummy sessions
+fn filled_server_session_cache(capacity: usize, entries: usize) -> Arc<ServerSessionMemoryCache> {
+    let server_cache = ServerSessionMemoryCache::new(capacity);
+    for i in 0..entries {
+        let mut session_id = [0u8; 32];
+        session_id[..8].copy_from_slice(&(i as u64).to_be_bytes());
+        server_cache.put(session_id.to_vec(), vec![0; FILLER_SESSION_SIZE]);
+    }
+    server_cache
+}
+
+/// Fills the session caches with `entries` unrelated sessions and stores one to resume. With
+/// `ResumptionParam::No`, the client config is a fresh one, so the next handshake is a full one
+/// whose session the server inserts (a TLS 1.2 resumption only looks the session up).
+fn primed_filled_cache_configs(
+    params: &BenchmarkParam,
+    entries: usize,
+    fill: CacheFill,
+    resume: ResumptionParam,
+) -> (Arc<ClientConfig<Ring>>, Arc<ServerConfig<Ring>>) {
+    let capacity = match fill {
+        CacheFill::Spare => 2 * entries,
+        CacheFill::Full => entries,
+    };
+
+    let server_cache = filled_server_session_cache(capacity, entries);
+
+    let client_cache = ClientSessionMemoryCache::new(capacity);
+    for i in 0..entries {
+        let server_name = format!("server{i}.example.com")
+            .as_str()
+            .try_into()
+            .unwrap();
+        client_cache.set_kx_hint(&server_name, NamedGroup::X25519);
+    }
+
+    let mut client_config = make_client_config(params, ClientAuth::No, ResumptionParam::SessionID);
+    client_config.resumption = Resumption::store(Arc::new(client_cache));
+    let client_config = Arc::new(client_config);
+
+    let mut server_config =
+        make_server_config(params, ClientAuth::No, ResumptionParam::SessionID, None);
+    server_config.session_storage = server_cache;
+    let server_config = Arc::new(server_config);
+
+    let (mut client, mut server) = connect(Arc::clone(&client_config), Arc::clone(&server_config));
+    do_handshake(&mut Transport::new(), &mut client, &mut server);
+
+    let client_config = match resume {
+        ResumptionParam::No => Arc::new(make_client_config(params, ClientAuth::No, resume)),
+        _ => client_config,
+    };
+
+    (client_config, server_config)
+}
+
+fn bench_primed_filled_cache(
+    params: &BenchmarkParam,
+    entries: usize,
+    fill: CacheFill,
+    resume: ResumptionParam,
+) {
+    black_box((
+        primed_filled_cache_configs(params, entries, fill, resume),
+        Transport::new(),
+    ));
+}
+
+fn bench_handshake_filled_cache(
+    params: &BenchmarkParam,
+    entries: usize,
+    fill: CacheFill,
+    resume: ResumptionParam,
+) {
+    let (client_config, server_config) = primed_filled_cache_configs(params, entries, fill, resume);
+
+    let (mut client, mut server) = connect(client_config, server_config);
+    do_handshake(&mut Transport::new(), &mut client, &mut server);
+}
+
+/// The ticket offered by the client when resuming with tickets, as seen by the server
+#[derive(PartialEq, Clone, Copy)]
+enum TicketScenario {
+    /// The ticket was encrypted with the server's current key
+    Valid,
+    /// The server has rotated its key since issuing the ticket, so the previous key decrypts it
+    Rotated,
+    /// The ticket was encrypted with a key the server does not know (anymore), so the server falls
+    /// back to a full handshake
+    Invalid,
+}
+
+impl TicketScenario {
+    fn label(&self) -> &'static str {
+        match *self {
+            Self::Valid => "valid",
+            Self::Rotated => "rotated",
+            Self::Invalid => "invalid",
+        }
+    }
+}
+
+/// Like the ticketer returned by `Ticketer::new` after one key rotation
+struct RotatedTicketer {
+    current: Arc<dyn ProducesTickets>,
+    previous: Arc<dyn ProducesTickets>,
+}
+
+impl ProducesTickets for RotatedTicketer {
+    fn enabled(&self) -> bool {
+        true
+    }
+
+    fn lifetime(&self) -> u32 {
+        self.current.lifetime()
+    }
+
+    fn encrypt(&self, plain: &[u8]) -> Option<Vec<u8>> {
+        self.current.encrypt(plain)
+    }
+
+    fn decrypt(&self, cipher: &[u8]) -> Option<Vec<u8>> {
+        self.current
+            .decrypt(cipher)
+            .or_else(|| self.previous.decrypt(cipher))
+    }
+}
+
+impl fmt::Debug for RotatedTicketer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RotatedTicketer").finish_non_exhaustive()
+    }
+}
+
+/// The ticket operation whose instructions are counted by `MeasuredTicketer`
+#[derive(PartialEq, Clone, Copy)]
+enum TicketOp {
+    Encrypt,
+    Decrypt,
+}
+
+/// Only counts the instructions of one operation; instrumentation must be stopped otherwise
+struct MeasuredTicketer {
+    inner: Arc<dyn ProducesTickets>,
+    measured: TicketOp,
+    /// Whether a ticket was decrypted successfully, meaning the handshake resumed
+    decrypted: AtomicBool,
+}
+
+impl ProducesTickets for MeasuredTicketer {
+    fn enabled(&self) -> bool {
+        self.inner.enabled()
+    }
+
+    fn lifetime(&self) -> u32 {
+        self.inner.lifetime()
+    }
+
+    fn encrypt(&self, plain: &[u8]) -> Option<Vec<u8>> {
+        bench_lib::measure_if(self.measured == TicketOp::Encrypt, || {
+            self.inner.encrypt(plain)
+        })
+    }
+
+    fn decrypt(&self, cipher: &[u8]) -> Option<Vec<u8>> {
+        let plain = bench_lib::measure_if(self.measured == TicketOp::Decrypt, || {
+            self.inner.decrypt(cipher)
+        });
+        if plain.is_some() {
+            self.decrypted.store(true, Ordering::Relaxed);
+        }
+        plain
+    }
+}
+
+impl fmt::Debug for MeasuredTicketer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MeasuredTicketer").finish_non_exhaustive()
+    }
+}
+
+/// Creates a client config holding a ticket issued by a full handshake, and the ticketer the server
+/// uses when the client comes back with that ticket
+fn primed_ticket_configs(
+    params: &BenchmarkParam,
+    scenario: TicketScenario,
+) -> (Arc<ClientConfig<Ring>>, Arc<dyn ProducesTickets>) {
+    let client_config = Arc::new(make_client_config(
+        params,
+        ClientAuth::No,
+        ResumptionParam::Tickets,
+    ));
+    let server_config = Arc::new(make_server_config(
+        params,
+        ClientAuth::No,
+        ResumptionParam::Tickets,
+        None,
+    ));
+
+    let (mut client, mut server) = connect(Arc::clone(&client_config), Arc::clone(&server_config));
+    // `bench_ticket_op` has no setup to subtract, so the priming traffic must not be recorded
+    run_handshake(&mut Transport::new(), &mut client, &mut server);
+
+    let issuing_ticketer = Arc::clone(&server_config.ticketer);
+    let resuming_ticketer = match scenario {
+        TicketScenario::Valid => issuing_ticketer,
+        TicketScenario::Rotated => Arc::new(RotatedTicketer {
+            current: Ticketer::new().unwrap(),
+            previous: issuing_ticketer,
+        }),
+        TicketScenario::Invalid => Ticketer::new().unwrap(),
+    };
+
+    (client_config, resuming_ticketer)
+}
+
+/// Returns a server config that uses the provided ticketer
+fn ticket_server_config(
+    params: &BenchmarkParam,
+    ticketer: Arc<dyn ProducesTickets>,
+) -> Arc<ServerConfig<Ring>> {
+    let mut server_config =
+        make_server_config(params, ClientAuth::No, ResumptionParam::Tickets, None);
+    server_config.ticketer = ticketer;
+    // TLS 1.3 servers send several tickets by default, TLS 1.2 servers a single one
+    server_config.send_tls13_tickets = 1;
+    Arc::new(server_config)
+}
+
+fn bench_primed_tickets(params: &BenchmarkParam, scenario: TicketScenario) {
+    let (client_config, ticketer) = primed_ticket_configs(params, scenario);
+    black_box((
+        client_config,
+        ticket_server_config(params, ticketer),
+        Transport::new(),
+    ));
+}
+
+fn bench_handshake_ticket(params: &BenchmarkParam, scenario: TicketScenario) {
+    let (client_config, ticketer) = primed_ticket_configs(params, scenario);
+    let server_config = ticket_server_config(params, ticketer);
+
+    let (mut client, mut server) = connect(client_config, server_config);
+    do_handshake(&mut Transport::new(), &mut client, &mut server);
+}
+
+/// Only counts the instructions of the server's ticketer on the provided operation
+fn bench_ticket_op(params: &BenchmarkParam, scenario: TicketScenario, measured: TicketOp) {
+    bench_lib::stop_instrumentation();
+
+    let (client_config, ticketer) = primed_ticket_configs(params, scenario);
+    let ticketer = Arc::new(MeasuredTicketer {
+        inner: ticketer,
+        measured,
+        decrypted: AtomicBool::new(false),
+    });
+    let server_config = ticket_server_config(params, Arc::clone(&ticketer) as _);
+
+    let (mut client, mut server) = connect(client_config, server_config);
+    run_handshake(&mut Transport::new(), &mut client, &mut server);
+
+    let resumed = scenario != TicketScenario::Invalid;
+    assert_eq!(ticketer.decrypted.load(Ordering::Relaxed), resumed);
+
+    drop(client);
+    drop(server);
+    bench_lib::start_instrumentation();
+}
+
+#[derive(PartialEq, Clone, Copy)]
+enum Side {
+    Client,
+    Server,
+}
+
+impl Side {
+    fn label(&self) -> &'static str {
+        match *self {
+            Self::Client => "client",
+            Self::Server => "server",
+        }
+    }
+}
+
+/// Only counts the instructions of the provided side, including creating its connection (where a
+/// client produces its ClientHello)
+fn bench_handshake_side(
+    params: &BenchmarkParam,
+    clientauth: ClientAuth,
+    resume: ResumptionParam,
+    side: Side,
+) {
+    bench_lib::stop_instrumentation();
+
+    let client_config = Arc::new(make_client_config(params, clientauth, resume));
+    let server_config = Arc::new(make_server_config(params, clientauth, resume, None));
+    let mut transport = Transport::new();
+
+    // Store a session in the configs, so the measured handshake actually resumes
+    if resume != ResumptionParam::No {
+        let (mut client, mut server) =
+            connect(Arc::clone(&client_config), Arc::clone(&server_config));
+        run_handshake(&mut transport, &mut client, &mut server);
+    }
+
+    // The client generates its key shares and queues the ClientHello when it is created
+    let server_name = "localhost".try_into().unwrap();
+    let mut client = bench_lib::measure_if(side == Side::Client, || {
+        ClientConnection::new(client_config, server_name).unwrap()
+    });
+    let mut server = bench_lib::measure_if(side == Side::Server, || {
+        ServerConnection::new(server_config).unwrap()
+    });
+
+    let (client_to_server, server_to_client) = match side {
+        Side::Client => (Measured::Left, Measured::Right),
+        Side::Server => (Measured::Right, Measured::Left),
+    };
+
+    let mut traffic = HandshakeTraffic::default();
+    loop {
+        traffic.client_sent(transport.transfer_measured(
+            &mut client,
+            &mut server,
+            None,
+            client_to_server,
+        ));
+        let client_handshaking = client.is_handshaking();
+        traffic.server_sent(
+            transport.transfer_measured(&mut server, &mut client, None, server_to_client),
+            client_handshaking,
+        );
+        if !server.is_handshaking() && !client.is_handshaking() {
+            break;
+        }
+    }
+    traffic.record();
+
+    drop(client);
+    drop(server);
+    drop(transport);
+    bench_lib::start_instrumentation();
+}
+
+/// Keeps the configs, and optionally one of the connections, alive so their heap is reported as
+/// retained
+fn bench_idle_connection_memory(
+    params: &BenchmarkParam,
+    side: Side,
+    buffer_limit: BufferLimit,
+    retain_connection: bool,
+) {
+    let client_config = Arc::new(make_client_config(
+        params,
+        ClientAuth::No,
+        ResumptionParam::SessionID,
+    ));
+    let server_config = Arc::new(make_server_config(
+        params,
+        ClientAuth::No,
+        ResumptionParam::SessionID,
+        None,
+    ));
+
+    let (mut client, mut server) = connect(Arc::clone(&client_config), Arc::clone(&server_config));
+    buffer_limit.apply(&mut client);
+    buffer_limit.apply(&mut server);
+
+    do_handshake(&mut Transport::new(), &mut client, &mut server);
+
+    bench_lib::retain((client_config, server_config));
+    if retain_connection {
+        match side {
+            Side::Client => bench_lib::retain(client),
+            Side::Server => bench_lib::retain(server),
+        }
+    }
+}
+
+/// Keeps a server config with a full session cache alive, or one without a cache if
+/// `session_cache_size` is zero
+fn bench_server_config_memory(params: &BenchmarkParam, session_cache_size: usize) {
+    let resume = match session_cache_size {
+        0 => ResumptionParam::No,
+        _ => ResumptionParam::SessionID,
+    };
+
+    let mut server_config = make_server_config(params, ClientAuth::No, resume, None);
+    if session_cache_size > 0 {
+        server_config.session_storage =
+            filled_server_session_cache(session_cache_size, session_cache_size);
+    }
+
+    bench_lib::retain(server_config);
+}
+
+/// TLS data exchanged during a handshake
+#[derive(Default)]
+struct HandshakeTraffic {
+    client_bytes: u64,
+    server_bytes: u64,
+    flights: u64,
+    /// Server flights the client had to wait for before completing the handshake
+    round_trips: u64,
+}
+
+impl HandshakeTraffic {
+    fn client_sent(&mut self, bytes: usize) {
+        if bytes > 0 {
+            self.client_bytes += bytes as u64;
+            self.flights += 1;
+        }
+    }
+
+    /// Accounts for a server flight, which only completes a round trip if the client was still
+    /// handshaking when it was sent (unlike TLS 1.3 session tickets, for instance)
+    fn server_sent(&mut self, bytes: usize, client_handshaking: bool) {
+        if bytes > 0 {
+            self.server_bytes += bytes as u64;
+            self.flights += 1;
+            if client_handshaking {
+                self.round_trips += 1;
+            }
+        }
+    }
+
+    /// Adds the traffic of another handshake
+    fn add(&mut self, other: &Self) {
+        self.client_bytes += other.client_bytes;
+        self.server_bytes += other.server_bytes;
+        self.flights += other.flights;
+        self.round_trips += other.round_trips;
+    }
+
+    /// Reports the traffic as metrics of the running benchmark
+    fn record(&self) {
+        bench_lib::record_metric("client bytes", self.client_bytes);
+        bench_lib::record_metric("server bytes", self.server_bytes);
+        bench_lib::record_metric("flights", self.flights);
+        bench_lib::record_metric("round trips", self.round_trips);
+    }
+
+    /// Like `record`, for the traffic of several handshakes added together
+    fn record_totals(&self) {
+        bench_lib::record_metric("total client bytes", self.client_bytes);
+        bench_lib::record_metric("total server bytes", self.server_bytes);
+        bench_lib::record_metric("total flights", self.flights);
+        bench_lib::record_metric("total round trips", self.round_trips);
+    }
+}
+
+/// Runs the handshake and records the resulting traffic as metrics of the running benchmark
+fn do_handshake(
+    transport: &mut Transport,
+    client: &mut ClientConnection,
+    server: &mut ServerConnection,
+) {
+    run_handshake(transport, client, server).record();
+}
+
+/// Runs the handshake, returning the traffic it caused without recording it
+fn run_handshake(
+    transport: &mut Transport,
+    client: &mut ClientConnection,
+    server: &mut ServerConnection,
+) -> HandshakeTraffic {
+    let mut traffic = HandshakeTraffic::default();
+    loop {
+        traffic.client_sent(transport.transfer(client, server, None));
+        let client_handshaking = client.is_handshaking();
+        traffic.server_sent(transport.transfer(server, client, None), client_handshaking);
+        if !server.is_handshaking() && !client.is_handshaking() {
+            break;
+        }
+    }
+    traffic
+}
+
+/// Creates a client and a server without buffer limits and runs the handshake between them
+fn established_connection(
+    params: &BenchmarkParam,
+    max_fragment_size: Option<usize>,
+    transport: &mut Transport,
+) -> (ClientConnection, ServerConnection) {
+    let client_config = Arc::new(make_client_config(
+        params,
+        ClientAuth::No,
+        ResumptionParam::No,
+    ));
+    let server_config = Arc::new(make_server_config(
+        params,
+        ClientAuth::No,
+        ResumptionParam::No,
+        max_fragment_size,
+    ));
+
+    let (mut client, mut server) = connect(client_config, Arc::clone(&server_config));
+    client.set_buffer_limit(None);
+    server.set_buffer_limit(None);
+
+    do_handshake(transport, &mut client, &mut server);
+    (client, server)
+}
+
+fn bench_established_connection(params: &BenchmarkParam, max_fragment_size: Option<usize>) {
+    let mut transport = Transport::new();
+    black_box(established_connection(
+        params,
+        max_fragment_size,
+        &mut transport,
+    ));
+}
+
+/// Mirrors rustls' fragmentation, where `max_fragment_size` includes the 5-byte record header
+fn record_count(plaintext_size: u64, max_fragment_size: Option<usize>) -> u64 {
+    let max_plaintext_per_record = match max_fragment_size {
+        Some(size) => size as u64 - 5,
+        None => 16384,
+    };
+    plaintext_size.div_ceil(max_plaintext_per_record)
+}
+
+fn bench_transfer(params: &BenchmarkParam, plaintext_size: u64, max_fragment_size: Option<usize>) {
+    let mut transport = Transport::new();
+    let (mut client, mut server) =
+        established_connection(params, max_fragment_size, &mut transport);
+
+    let mut buf = Vec::new();
+    buf.resize(plaintext_size as usize, 0u8);
+
+    server.writer().write_all(&buf).unwrap();
+    transport.transfer(&mut server, &mut client, Some(buf.len()));
+}
+
+fn bench_transfer_direct(params: &BenchmarkParam, plaintext_size: u64) {
+    let mut transport = Transport::new();
+    let (mut client, mut server) = established_connection(params, Some(16384), &mut transport);
+
+    let buf = vec![0u8; plaintext_size as usize];
+    server.writer().write_all(&buf).unwrap();
+    transport.transfer_direct(&mut server, &mut client, Some(buf.len()));
+}
+
+/// Sends `plaintext_size` bytes from the server, handed to rustls in a single `write_vectored`
+/// call with slices of `slice_size` bytes
+fn bench_transfer_vectored(params: &BenchmarkParam, plaintext_size: u64, slice_size: usize) {
+    let mut transport = Transport::new();
+    let (mut client, mut server) = established_connection(params, None, &mut transport);
+
+    let buf = vec![0u8; plaintext_size as usize];
+    let slices: Vec<_> = buf.chunks(slice_size).map(IoSlice::new).collect();
+    let written = server.writer().write_vectored(&slices).unwrap();
+    assert_eq!(written, buf.len());
+
+    transport.transfer(&mut server, &mut client, Some(buf.len()));
+}
+
+/// Sends `plaintext_size` bytes from the server in writes of `chunk_size` bytes, transferring the
+/// TLS data to the client after every write, like an application streaming its output
+fn bench_transfer_chunked(params: &BenchmarkParam, plaintext_size: u64, chunk_size: usize) {
+    let mut transport = Transport::new();
+    let (mut client, mut server) = established_connection(params, None, &mut transport);
+
+    let buf = vec![0u8; plaintext_size as usize];
+    for chunk in buf.chunks(chunk_size) {
+        server.writer().write_all(chunk).unwrap();
+        transport.transfer(&mut server, &mut client, Some(chunk.len()));
+    }
+}
+
+/// The server flushes whenever its buffer limit is reached, and the flush rounds are recorded
+fn bench_transfer_limited(params: &BenchmarkParam, plaintext_size: u64, buffer_limit: BufferLimit) {
+    let mut transport = Transport::new();
+    let (mut client, mut server) = established_connection(params, None, &mut transport);
+    buffer_limit.apply(&mut client);
+    buffer_limit.apply(&mut server);
+
+    let buf = vec![0u8; plaintext_size as usize];
+    let mut sent = 0;
+    let mut flush_rounds = 0;
+    while sent < buf.len() {
+        let written = server.writer().write(&buf[sent..]).unwrap();
+        assert!(
+            written > 0,
+            "buffer limit leaves no room for application data"
+        );
+        transport.transfer(&mut server, &mut client, Some(written));
+        sent += written;
+        flush_rounds += 1;
+    }
+
+    bench_lib::record_metric("flush rounds", flush_rounds);
+}
+
+/// Shuts down an established connection, with both sides sending and processing close_notify
+fn bench_close_notify(params: &BenchmarkParam) {
+    let mut transport = Transport::new();
+    let (mut client, mut server) = established_connection(params, None, &mut transport);
+
+    client.send_close_notify();
+    transport.transfer(&mut client, &mut server, None);
+    assert!(server.process_new_packets().unwrap().peer_has_closed());
+
+    server.send_close_notify();
+    transport.transfer(&mut server, &mut client, None);
+    assert!(client.process_new_packets().unwrap().peer_has_closed());
+}
+
+/// Sends a record with a corrupted authentication tag, which the server rejects with a fatal
+/// alert that the client then processes
+fn bench_corrupted_record(params: &BenchmarkParam) {
+    let mut transport = Transport::new();
+    let (mut client, mut server) = established_connection(params, None, &mut transport);
+
+    client
+        .writer()
+        .write_all(b"GET / HTTP/1.1\r\n\r\n")
+        .unwrap();
+    let mut record = write_flight(&mut client);
+    *record.last_mut().unwrap() ^= 1;
+
+    let err = try_read_flight(&mut server, &record).unwrap_err();
+    assert_eq!(err, rustls::Error::DecryptError);
+
+    let alert = write_flight(&mut server);
+    let err = try_read_flight(&mut client, &alert).unwrap_err();
+    assert_eq!(
+        err,
+        rustls::Error::AlertReceived(AlertDescription::BadRecordMac)
+    );
+}
+
+fn bench_upload(params: &BenchmarkParam, plaintext_size: u64) {
+    let mut transport = Transport::new();
+    let (mut client, mut server) = established_connection(params, None, &mut transport);
+
+    let buf = vec![0u8; plaintext_size as usize];
+    client.writer().write_all(&buf).unwrap();
+    transport.transfer(&mut client, &mut server, Some(buf.len()));
+}
+
+/// Sends `round_trips` requests from the client, each answered by a response from the server
+fn bench_request_response(
+    params: &BenchmarkParam,
+    round_trips: usize,
+    request_size: usize,
+    response_size: usize,
+) {
+    let mut transport = Transport::new();
+    let (mut client, mut server) = established_connection(params, None, &mut transport);
+
+    let request = vec![0u8; request_size];
+    let response = vec![0u8; response_size];
+
+    for _ in 0..round_trips {
+        client.writer().write_all(&request).unwrap();
+        transport.transfer(&mut client, &mut server, Some(request.len()));
+
+        server.writer().write_all(&response).unwrap();
+        transport.transfer(&mut server, &mut client, Some(response.len()));
+    }
+}
+
+/// Counts the handshake traffic through the client's socket; a flight ends when the direction
+/// changes
+struct TrafficSocket<'a> {
+    sock: &'a mut UnixStream,
+    traffic: HandshakeTraffic,
+    /// Bytes of the flight in progress, written by the client
+    written: usize,
+    /// Bytes of the flight in progress, read from the server
+    read: usize,
+}
+
+impl<'a> TrafficSocket<'a> {
+    fn new(sock: &'a mut UnixStream) -> Self {
+        Self {
+            sock,
+            traffic: HandshakeTraffic::default(),
+            written: 0,
+            read: 0,
+        }
+    }
+
+    /// The client only reads during the handshake, so every server flight is a round trip
+    fn finish(mut self) -> HandshakeTraffic {
+        self.traffic.client_sent(self.written);
+        self.traffic.server_sent(self.read, true);
+        self.traffic
+    }
+}
+
+impl Read for TrafficSocket<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.sock.read(buf)?;
+        self.traffic.client_sent(self.written);
+        self.written = 0;
+        self.read += read;
+        Ok(read)
+    }
+}
+
+impl Write for TrafficSocket<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.sock.write(buf)?;
+        self.traffic.server_sent(self.read, true);
+        self.read = 0;
+        self.written += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sock.flush()
+    }
+}
+
+/// Uses rustls' blocking I/O helpers over a Unix socket pair, with the server on its own thread, so
+/// the count includes syscalls and the handover between threads
+fn bench_socket(params: &BenchmarkParam, handshake: bool, plaintext_size: u64) {
+    let (mut client, server) = new_connection(params, ClientAuth::No, ResumptionParam::No);
+    let (mut client_sock, server_sock) = UnixStream::pair().unwrap();
+
+    let server_thread = thread::spawn(move || {
+        if !handshake {
+            return;
+        }
+
+        let mut server = StreamOwned::new(server, server_sock);
+        complete_handshake(&mut server.conn, &mut server.sock);
+        let received = io::copy(&mut (&mut server).take(plaintext_size), &mut io::sink()).unwrap();
+        assert_eq!(received, plaintext_size);
+    });
+
+    let mut traffic = None;
+    if handshake {
+        let mut sock = TrafficSocket::new(&mut client_sock);
+        complete_handshake(&mut client, &mut sock);
+        traffic = Some(sock.finish());
+
+        let chunk = [0u8; SOCKET_WRITE_SIZE];
+        let mut stream = Stream::new(&mut client, &mut client_sock);
+        let mut left = plaintext_size;
+        while left > 0 {
+            let size = left.min(SOCKET_WRITE_SIZE as u64);
+            stream.write_all(&chunk[..size as usize]).unwrap();
+            left -= size;
+        }
+        stream.flush().unwrap();
+    }
+
+    server_thread.join().unwrap();
+
+    // Recording stops instrumentation, which would also hide work done by the server thread
+    if let Some(traffic) = traffic {
+        traffic.record();
+    }
+}
+
+/// Writes all pending TLS data of the connection into a new buffer
+fn write_flight<C, S>(conn: &mut C) -> Vec<u8>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<S>>,
+    S: SideData,
+{
+    let mut flight = Vec::new();
+    while conn.wants_write() {
+        conn.write_tls(&mut flight).unwrap();
+    }
+    flight
+}
+
+/// Feeds the provided TLS data into the connection and processes it
+fn read_flight<C, S>(conn: &mut C, flight: &[u8])
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<S>>,
+    S: SideData,
+{
+    try_read_flight(conn, flight).unwrap();
+}
+
+/// Like `read_flight`, but returns the error that made the connection reject the data
+fn try_read_flight<C, S>(conn: &mut C, mut flight: &[u8]) -> Result<(), rustls::Error>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<S>>,
+    S: SideData,
+{
+    while !flight.is_empty() {
+        conn.read_tls(&mut flight).unwrap();
+        conn.process_new_packets()?;
+    }
+    Ok(())
+}
+
+fn transcript_path(tls: &str) -> PathBuf {
+    PathBuf::from(format!("transcripts/handshake_no_resume_{tls}.bin"))
+}
+
+/// Runs a handshake and records the flights exchanged by the client and the server
+fn record_handshake(params: &BenchmarkParam) -> Transcript {
+    let (mut client, mut server) = new_connection(params, ClientAuth::No, ResumptionParam::No);
+    let mut transcript = Transcript::default();
+
+    loop {
+        let flight = write_flight(&mut client);
+        read_flight(&mut server, &flight);
+        transcript.push(Direction::ClientToServer, flight);
+
+        let flight = write_flight(&mut server);
+        read_flight(&mut client, &flight);
+        transcript.push(Direction::ServerToClient, flight);
+
+        if !server.is_handshaking() && !client.is_handshaking() {
+            break;
+        }
+    }
+
+    transcript
+}
+
+/// Loads the client's first flight from the transcript and creates a server to replay it against
+fn replay_setup(params: &BenchmarkParam, transcript: &Path) -> (Vec<u8>, ServerConnection) {
+    let transcript = Transcript::load(transcript).unwrap();
+    let client_hello = transcript
+        .first_flight(Direction::ClientToServer)
+        .expect("transcript contains no client flight")
+        .to_vec();
+
+    let server_config = Arc::new(make_server_config(
+        params,
+        ClientAuth::No,
+        ResumptionParam::No,
+        None,
+    ));
+    let server = ServerConnection::new(server_config).unwrap();
+    (client_hello, server)
+}
+
+fn bench_replay_setup(params: &BenchmarkParam, transcript: &Path) {
+    black_box(replay_setup(params, transcript));
+}
+
+/// Later flights can't be replayed, since they depend on the original server's randomness
+fn bench_replay_client_hello(params: &BenchmarkParam, transcript: &Path) {
+    let (client_hello, mut server) = replay_setup(params, transcript);
+    read_flight(&mut server, &client_hello);
+    let server_flight = black_box(write_flight(&mut server));
+
+    let mut traffic = HandshakeTraffic::default();
+    traffic.client_sent(client_hello.len());
+    traffic.server_sent(server_flight.len(), true);
+    traffic.record();
+}
+
+/// All threads share one server config, so contention on its session cache and ticketer shows
+fn concurrent_handshakes(
+    params: &BenchmarkParam,
+    resume: ResumptionParam,
+    threads: usize,
+    handshakes_per_thread: u32,
+) -> Duration {
+    let server_config = Arc::new(make_server_config(params, ClientAuth::No, resume, None));
+    let client_configs: Vec<_> = (0..threads)
+        .map(|_| Arc::new(make_client_config(params, ClientAuth::No, resume)))
+        .collect();
+
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for client_config in &client_configs {
+            let server_config = &server_config;
+            scope.spawn(move || {
+                let mut transport = Transport::new();
+                for _ in 0..handshakes_per_thread {
+                    let (mut client, mut server) =
+                        connect(Arc::clone(client_config), Arc::clone(server_config));
+                    // Recording metrics would add a lock shared between the threads
+                    run_handshake(&mut transport, &mut client, &mut server);
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+/// Cachegrind serializes threads, so contention can only be observed with wall-clock time
+fn run_wall_clock(params: &BenchmarkParam) {
+    let tls = format!("{:?}", params.version);
+    let max_threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let thread_counts: Vec<_> = itertools::iterate(1, |threads| threads * 2)
+        .take_while(|&threads| threads <= max_threads)
+        .collect();
+
+    for resume in [
+        ResumptionParam::No,
+        ResumptionParam::SessionID,
+        ResumptionParam::Tickets,
+    ] {
+        let mut single_thread_rate = None;
+        for &threads in &thread_counts {
+            let elapsed =
+                concurrent_handshakes(params, resume, threads, WALL_CLOCK_HANDSHAKES_PER_THREAD);
+            let handshakes = threads as f64 * WALL_CLOCK_HANDSHAKES_PER_THREAD as f64;
+            let rate = handshakes / elapsed.as_secs_f64();
+            let single_thread_rate = *single_thread_rate.get_or_insert(rate);
+
+            println!(
+                "{:.0} handshakes/s : concurrent_{}_{threads}_threads_{tls} ({:.2}x single thread)",
+                rate,
+                resume.label(),
+                rate / single_thread_rate
+            );
+        }
+    }
+}
+
+fn corpus_dir() -> PathBuf {
+    PathBuf::from("corpus")
+}
+
+/// Generates the corpus: mutations of the ClientHello recorded in the transcript fixture of each
+/// of the provided params, and inputs made of random bytes
+fn generate_corpus(params: &[fn() -> BenchmarkParam]) -> Vec<(String, Vec<u8>)> {
+    let mut inputs = corpus::random_inputs();
+    for param in params {
+        let tls = format!("{:?}", param().version);
+        let transcript = Transcript::load(&transcript_path(&tls)).unwrap();
+        let client_hello = transcript
+            .first_flight(Direction::ClientToServer)
+            .expect("transcript contains no client flight");
+        inputs.extend(corpus::mutations(
+            &format!("client_hello_{tls}"),
+            client_hello,
+        ));
+    }
+    inputs
+}
+
+/// Loads a corpus input and creates a server connection to feed it to
+fn corpus_setup(params: &BenchmarkParam, input: &Path) -> (Vec<u8>, ServerConnection) {
+    let input = fs::read(input).unwrap();
+    let server_config = Arc::new(make_server_config(
+        params,
+        ClientAuth::No,
+        ResumptionParam::No,
+        None,
+    ));
+    let server = ServerConnection::new(server_config).unwrap();
+    (input, server)
+}
+
+fn bench_corpus_setup(params: &BenchmarkParam, input: &Path) {
+    black_box(corpus_setup(params, input));
+}
+
+/// An input that leaves the server waiting for more data counts as accepted
+fn bench_corpus(params: &BenchmarkParam, input: &Path) {
+    let (input, mut server) = corpus_setup(params, input);
+
+    let mut input = input.as_slice();
+    let mut rejected = false;
+    while !input.is_empty() {
+        if server.read_tls(&mut input).is_err() || server.process_new_packets().is_err() {
+            rejected = true;
+            break;
+        }
+    }
+
+    if rejected {
+        bench_lib::record_metric("rejected", 1);
+    } else {
+        bench_lib::record_metric("accepted", 1);
+    }
+}
+
+fn add_benchmarks_for_params(benchmarks: &mut Vec<Benchmark>, get_param: fn() -> BenchmarkParam) {
+    let tls = format!("{:?}", get_param().version);
+    let all_resumption_params = [
+        ResumptionParam::No,
+        ResumptionParam::SessionID,
+        ResumptionParam::Tickets,
+    ];
+
+    // Benchmark handshake with and without resumption
+    for resumption_param in all_resumption_params {
+        benchmarks.extend([
+            Benchmark::new(
+                format!("new_conn_{}_{tls}", resumption_param.label()),
+                move || {
+                    bench_new_connection(
+                        &black_box(get_param()),
+                        black_box(ClientAuth::No),
+                        black_box(ResumptionParam::No),
+                    )
+                },
+            )
+            .hidden(),
+            Benchmark::new(
+                format!("handshake_{}_{tls}", resumption_param.label()),
+                move || {
+                    bench_handshake(
+                        &black_box(get_param()),
+                        black_box(ClientAuth::No),
+                        black_box(ResumptionParam::No),
+                    )
+                },
+            )
+            .exclude_setup_instructions(format!("new_conn_{}_{tls}", resumption_param.label())),
+        ])
+    }
+
+    // Benchmark the handshake work of the client and the server separately
+    for resumption_param in all_resumption_params {
+        for side in [Side::Client, Side::Server] {
+            benchmarks.push(Benchmark::new(
+                format!(
+                    "handshake_{}_{}_{tls}",
+                    side.label(),
+                    resumption_param.label()
+                ),
+                move || {
+                    bench_handshake_side(
+                        &black_box(get_param()),
+                        black_box(ClientAuth::No),
+                        black_box(resumption_param),
+                        black_box(side),
+                    )
+                },
+            ));
+        }
+    }
+
+    // Benchmark resumption with 0-RTT early data, which only exists in TLS 1.3
+    if get_param().version == &rustls::version::TLS13 {
+        benchmarks.extend([
+            Benchmark::new(format!("primed_early_data_{tls}"), move || {
+                bench_primed_early_data(&black_box(get_param()))
+            })
+            .hidden(),
+            Benchmark::new(format!("handshake_early_data_{tls}"), move || {
+                bench_handshake_early_data(&black_box(get_param()))
+            })
+            .exclude_setup_instructions(format!("primed_early_data_{tls}")),
+        ]);
+    }
+
+    // Benchmark ALPN negotiation with increasingly long protocol lists
+    for protocol_count in [1, 10, 100] {
+        benchmarks.extend(handshake_benchmarks(
+            format!("alpn_{protocol_count}_{tls}"),
+            get_param,
+            move |params| alpn_connection(params, black_box(protocol_count)),
+            |_, client, _| assert_eq!(client.alpn_protocol(), Some(&b"h2"[..])),
+        ));
+    }
+
+    // Benchmark virtual host selection by SNI among many names
+    for name_count in [1, 100, 10_000] {
+        benchmarks.extend(handshake_benchmarks(
+            format!("sni_{name_count}_{tls}"),
+            get_param,
+            move |params| sni_connection(params, black_box(name_count)),
+            |_, _, server| assert_eq!(server.server_name(), Some("localhost")),
+        ));
+    }
+
+    // Benchmark certificate resolvers. Only TLS 1.3 decouples the cipher suite from the key type,
+    // so with the TLS 1.2 params the key type resolvers both end up with the RSA certificate,
+    // which exercises the fallback.
+    for resolver in [
+        CertResolver::Sni,
+        CertResolver::KeyType(KeyType::Rsa),
+        CertResolver::KeyType(KeyType::Ecdsa),
+    ] {
+        benchmarks.extend(handshake_benchmarks(
+            format!("resolver_{}_{tls}", resolver.label()),
+            get_param,
+            move |params| resolver_connection(params, black_box(resolver)),
+            move |params, client, _| {
+                let expected_chain = resolver.expected_key_type(params).get_chain();
+                assert_eq!(client.peer_certificates().unwrap()[0], expected_chain[0]);
+            },
+        ));
+    }
+
+    // Benchmark handshakes where the server staples an OCSP response or SCTs to its certificate
+    for stapling in [Stapling::Ocsp, Stapling::Sct, Stapling::OcspAndSct] {
+        benchmarks.extend(handshake_benchmarks(
+            format!("stapled_{}_{tls}", stapling.label()),
+            get_param,
+            move |params| stapled_connection(params, black_box(stapling)),
+            |_, _, _| {},
+        ));
+    }
+
+    // Benchmark the work the server does on handshakes that fail, which bounds the cost of
+    // rejecting unwanted clients
+    for failure in [
+        HandshakeFailure::UnknownCa,
+        HandshakeFailure::WrongServerName,
+        HandshakeFailure::ExpiredCertificate,
+        HandshakeFailure::NoSharedCipherSuite,
+        HandshakeFailure::NoSharedVersion,
+    ] {
+        benchmarks.push(Benchmark::new(
+            format!("handshake_fail_server_{}_{tls}", failure.label()),
+            move || bench_handshake_failure(&black_box(get_param()), black_box(failure)),
+        ));
+    }
+
+    // Benchmark the steady-state cost of handshakes on long-lived configs
+    for resumption_param in all_resumption_params {
+        let resume = resumption_param.label();
+        benchmarks.push(
+            Benchmark::new(format!("shared_configs_{resume}_{tls}"), move || {
+                bench_shared_configs(
+                    &black_box(get_param()),
+                    black_box(resumption_param),
+                    black_box(0),
+                )
+            })
+            .hidden(),
+        );
+
+        for connections in [1, 10, 100] {
+            benchmarks.push(
+                Benchmark::new(
+                    format!("handshake_shared_{resume}_{connections}_{tls}"),
+                    move || {
+                        bench_shared_configs(
+                            &black_box(get_param()),
+                            black_box(resumption_param),
+                            black_box(connections),
+                        )
+                    },
+                )
+                .exclude_setup_instructions(format!("shared_configs_{resume}_{tls}"))
+                .report_per("connection", connections),
+            );
+        }
+    }
+
+    // Benchmark resumptions and full handshakes against session caches holding many other
+    // sessions, with and without evictions
+    for entries in [128, 10_000, 100_000] {
+        for fill in [CacheFill::Spare, CacheFill::Full] {
+            for resume in [ResumptionParam::SessionID, ResumptionParam::No] {
+                let fill_label = fill.label();
+                let handshake = match resume {
+                    ResumptionParam::No => "no_resume",
+                    _ => "resume",
+                };
+                let setup = format!("primed_cache_{handshake}_{entries}_{fill_label}_{tls}");
+                benchmarks.extend([
+                    Benchmark::new(setup.clone(), move || {
+                        bench_primed_filled_cache(
+                            &black_box(get_param()),
+                            black_box(entries),
+                            black_box(fill),
+                            black_box(resume),
+                        )
+                    })
+                    .hidden(),
+                    Benchmark::new(
+                        format!("handshake_{handshake}_cache_{entries}_{fill_label}_{tls}"),
+                        move || {
+                            bench_handshake_filled_cache(
+                                &black_box(get_param()),
+                                black_box(entries),
+                                black_box(fill),
+                                black_box(resume),
+                            )
+                        },
+                    )
+                    .exclude_setup_instructions(setup),
+                ]);
+            }
+        }
+    }
+
+    // Benchmark handshake and transfer over a real socket, including the syscalls
+    benchmarks.extend([
+        Benchmark::new(format!("socket_setup_{tls}"), move || {
+            bench_socket(&black_box(get_param()), black_box(false), black_box(0))
+        })
+        .hidden(),
+        Benchmark::new(format!("socket_handshake_{tls}"), move || {
+            bench_socket(&black_box(get_param()), black_box(true), black_box(0))
+        })
+        .exclude_setup_instructions(format!("socket_setup_{tls}")),
+    ]);
+    for plaintext_size in [1024, 1024 * 1024, 16 * 1024 * 1024] {
+        benchmarks.push(
+            Benchmark::new(
+                format!("socket_transfer_{plaintext_size}_{tls}"),
+                move || {
+                    bench_socket(
+                        &black_box(get_param()),
+                        black_box(true),
+                        black_box(plaintext_size),
+                    )
+                },
+            )
+            .exclude_setup_instructions(format!("socket_handshake_{tls}"))
+            .report_per("byte", plaintext_size),
+        );
+    }
+
+    // Benchmark ticket-based resumption when the server has rotated its ticket key or no longer
+    // knows it, along with the cost of the ticket operations themselves
+    benchmarks.push(Benchmark::new(format!("ticket_encrypt_{tls}"), move || {
+        bench_ticket_op(
+            &black_box(get_param()),
+            black_box(TicketScenario::Valid),
+            black_box(TicketOp::Encrypt),
+        )
+    }));
+    for scenario in [
+        TicketScenario::Valid,
+        TicketScenario::Rotated,
+        TicketScenario::Invalid,
+    ] {
+        let scenario_label = scenario.label();
+        benchmarks.extend([
+            Benchmark::new(
+                format!("primed_tickets_{scenario_label}_{tls}"),
+                move || bench_primed_tickets(&black_box(get_param()), black_box(scenario)),
+            )
+            .hidden(),
+            Benchmark::new(
+                format!("handshake_ticket_{scenario_label}_{tls}"),
+                move || bench_handshake_ticket(&black_box(get_param()), black_box(scenario)),
+            )
+            .exclude_setup_instructions(format!("primed_tickets_{scenario_label}_{tls}")),
+            Benchmark::new(
+                format!("ticket_decrypt_{scenario_label}_{tls}"),
+                move || {
+                    bench_ticket_op(
+                        &black_box(get_param()),
+                        black_box(scenario),
+                        black_box(TicketOp::Decrypt),
+                    )
+                },
+            ),
+        ]);
+    }
+
+    // Benchmark the heap retained by idle connections and session caches, which is only possible
+    // when allocations are being counted
+    if bench_lib::allocations_counted() {
+        for buffer_limit in [BufferLimit::Default, BufferLimit::Unlimited] {
+            let limit = buffer_limit.label();
+            benchmarks.push(
+                Benchmark::new(format!("mem_idle_setup_{limit}_{tls}"), move || {
+                    bench_idle_connection_memory(
+                        &black_box(get_param()),
+                        black_box(Side::Client),
+                        black_box(buffer_limit),
+                        black_box(false),
+                    )
+                })
+                .hidden(),
+            );
+
+            for side in [Side::Client, Side::Server] {
+                benchmarks.push(
+                    Benchmark::new(
+                        format!("mem_idle_{}_{limit}_{tls}", side.label()),
+                        move || {
+                            bench_idle_connection_memory(
+                                &black_box(get_param()),
+                                black_box(side),
+                                black_box(buffer_limit),
+                                black_box(true),
+                            )
+                        },
+                    )
+                    .exclude_setup_instructions(format!("mem_idle_setup_{limit}_{tls}")),
+                );
+            }
+        }
+
+        benchmarks.push(
+            Benchmark::new(format!("mem_server_config_{tls}"), move || {
+                bench_server_config_memory(&black_box(get_param()), black_box(0))
+            })
+            .hidden(),
+        );
+        for session_cache_size in [128, 1024, 10_000, 100_000] {
+            benchmarks.push(
+                Benchmark::new(
+                    format!("mem_session_cache_{session_cache_size}_{tls}"),
+                    move || {
+                        bench_server_config_memory(
+                            &black_box(get_param()),
+                            black_box(session_cache_size),
+                        )
+                    },
+                )
+                .exclude_setup_instructions(format!("mem_server_config_{tls}"))
+                .report_per("session", session_cache_size as u64),
+            );
+        }
+    }
+
+    // Benchmark config construction and handshake with differently sized root stores
+    for root_count in [0, 10, 150, 1000] {
+        benchmarks.extend([
+            Benchmark::new(format!("synthetic_roots_{root_count}_{tls}"), move || {
+                bench_synthetic_roots(&black_box(get_param()), black_box(root_count))
+            })
+            .hidden(),
+            Benchmark::new(
+                format!("client_config_roots_{root_count}_{tls}"),
+                move || {
+                    bench_client_config_with_roots(&black_box(get_param()), black_box(root_count))
+                },
+            )
+            .exclude_setup_instructions(format!("synthetic_roots_{root_count}_{tls}")),
+        ]);
+        benchmarks.extend(handshake_benchmarks(
+            format!("roots_{root_count}_{tls}"),
+            get_param,
+            move |params| new_connection_with_roots(params, black_box(root_count)),
+            |_, _, _| {},
+        ));
+    }
+
+    // Benchmark data transfer
+    benchmarks.extend([
+        Benchmark::new(format!("transfer_no_resume_{tls}"), move || {
+            bench_transfer(
+                &black_box(get_param()),
+                black_box(1024 * 1024),
+                black_box(None),
+            )
+        })
+        .exclude_setup_instructions(format!("handshake_no_resume_{tls}"))
+        .report_per("byte", 1024 * 1024)
+        .report_per("record", record_count(1024 * 1024, None)),
+        Benchmark::new(format!("upload_no_resume_{tls}"), move || {
+            bench_upload(&black_box(get_param()), black_box(1024 * 1024))
+        })
+        .exclude_setup_instructions(format!("established_conn_{tls}")),
+    ]);
+
+    // Benchmark data transfer for a range of plaintext and fragment sizes, so the fixed cost per
+    // record can be told apart from the cost per byte
+    for max_fragment_size in [512, 4096, 16384] {
+        benchmarks.push(
+            Benchmark::new(
+                format!("established_conn_frag_{max_fragment_size}_{tls}"),
+                move || {
+                    bench_established_connection(
+                        &black_box(get_param()),
+                        black_box(Some(max_fragment_size)),
+                    )
+                },
+            )
+            .hidden(),
+        );
+
+        for plaintext_size in [1, 1024, 16 * 1024, 1024 * 1024, 16 * 1024 * 1024] {
+            benchmarks.push(
+                Benchmark::new(
+                    format!("transfer_{plaintext_size}_frag_{max_fragment_size}_{tls}"),
+                    move || {
+                        bench_transfer(
+                            &black_box(get_param()),
+                            black_box(plaintext_size),
+                            black_box(Some(max_fragment_size)),
+                        )
+                    },
+                )
+                .exclude_setup_instructions(format!(
+                    "established_conn_frag_{max_fragment_size}_{tls}"
+                ))
+                .report_per("byte", plaintext_size)
+                .report_per(
+                    "record",
+                    record_count(plaintext_size, Some(max_fragment_size)),
+                ),
+            );
+        }
+    }
+
+    // Benchmark data transfer where the application hands over its data in small pieces, either
+    // all at once through a vectored write or streamed with a transfer after every write
+    let streamed_size: u64 = 16 * 1024;
+    benchmarks.push(
+        Benchmark::new(format!("established_conn_{tls}"), move || {
+            bench_established_connection(&black_box(get_param()), black_box(None))
+        })
+        .hidden(),
+    );
+    for piece_size in [1, 100, 4096] {
+        let pieces = streamed_size.div_ceil(piece_size as u64);
+        benchmarks.extend([
+            Benchmark::new(
+                format!("transfer_vectored_{streamed_size}_slice_{piece_size}_{tls}"),
+                move || {
+                    bench_transfer_vectored(
+                        &black_box(get_param()),
+                        black_box(streamed_size),
+                        black_box(piece_size),
+                    )
+                },
+            )
+            .exclude_setup_instructions(format!("established_conn_{tls}"))
+            .report_per("byte", streamed_size)
+            .report_per("slice", pieces),
+            Benchmark::new(
+                format!("transfer_chunked_{streamed_size}_chunk_{piece_size}_{tls}"),
+                move || {
+                    bench_transfer_chunked(
+                        &black_box(get_param()),
+                        black_box(streamed_size),
+                        black_box(piece_size),
+                    )
+                },
+            )
+            .exclude_setup_instructions(format!("established_conn_{tls}"))
+            .report_per("byte", streamed_size)
+            .report_per("write", pieces),
+        ]);
+    }
+
+    // Benchmark data transfer under backpressure from buffer limits, which forces the writer to
+    // flush and resume many times
+    let limited_size = 1024 * 1024;
+    for buffer_limit in [
+        BufferLimit::Unlimited,
+        BufferLimit::Default,
+        BufferLimit::Bytes(16 * 1024),
+        BufferLimit::Bytes(4096),
+        BufferLimit::Bytes(1024),
+    ] {
+        benchmarks.push(
+            Benchmark::new(
+                format!("transfer_{limited_size}_{}_{tls}", buffer_limit.label()),
+                move || {
+                    bench_transfer_limited(
+                        &black_box(get_param()),
+                        black_box(limited_size),
+                        black_box(buffer_limit),
+                    )
+                },
+            )
+            .exclude_setup_instructions(format!("established_conn_{tls}"))
+            .report_per("byte", limited_size),
+        );
+    }
+
+    // Benchmark connection teardown, both orderly and through a fatal alert
+    benchmarks.extend([
+        Benchmark::new(format!("close_notify_{tls}"), move || {
+            bench_close_notify(&black_box(get_param()))
+        })
+        .exclude_setup_instructions(format!("established_conn_{tls}")),
+        Benchmark::new(format!("corrupted_record_{tls}"), move || {
+            bench_corrupted_record(&black_box(get_param()))
+        })
+        .exclude_setup_instructions(format!("established_conn_{tls}")),
+    ]);
+
+    // Benchmark data transfer without the pipe between connections, to be compared with the
+    // transfers above that use a 16384 byte fragment size
+    for plaintext_size in [1, 1024, 16 * 1024, 1024 * 1024, 16 * 1024 * 1024] {
+        benchmarks.push(
+            Benchmark::new(
+                format!("transfer_direct_{plaintext_size}_{tls}"),
+                move || bench_transfer_direct(&black_box(get_param()), black_box(plaintext_size)),
+            )
+            .exclude_setup_instructions(format!("established_conn_frag_16384_{tls}"))
+            .report_per("byte", plaintext_size)
+            .report_per("record", record_count(plaintext_size, Some(16384))),
+        );
+    }
+
+    // Benchmark a single server against the committed transcript fixture
+    let transcript = transcript_path(&tls);
+    if transcript.exists() {
+        let setup_transcript = transcript.clone();
+        benchmarks.extend([
+            Benchmark::new(format!("replay_setup_{tls}"), move || {
+                bench_replay_setup(&black_box(get_param()), &setup_transcript)
+            })
+            .hidden(),
+            Benchmark::new(format!("replay_client_hello_{tls}"), move || {
+                bench_replay_client_hello(&black_box(get_param()), &transcript)
+            })
+            .exclude_setup_instructions(format!("replay_setup_{tls}")),
+        ]);
+    } else {
+        eprintln!(
+            "Warning: {} is missing, skipping replay_client_hello_{tls}",
+            transcript.display()
+        );
+    }
+
+    // Benchmark a server parsing each input of the malformed input corpus. All inputs share the
+    // setup of the first one, since loading an input costs the same regardless of its contents.
+    let inputs = corpus::list(&corpus_dir()).unwrap_or_default();
+    if let Some(setup_input) = inputs.first().cloned() {
+        benchmarks.push(
+            Benchmark::new(format!("corpus_setup_{tls}"), move || {
+                bench_corpus_setup(&black_box(get_param()), &setup_input)
+            })
+            .hidden(),
+        );
+    } else {
+        eprintln!(
+            "Warning: {} is missing, skipping the corpus benchmarks",
+            corpus_dir().display()
+        );
+    }
+    for input in inputs {
+        let stem = input.file_stem().unwrap().to_string_lossy().into_owned();
+        benchmarks.push(
+            Benchmark::new(format!("corpus_{stem}_{tls}"), move || {
+                bench_corpus(&black_box(get_param()), &input)
+            })
+            .exclude_setup_instructions(format!("corpus_setup_{tls}")),
+        );
+    }
+
+    // Benchmark request/response traffic, where many small records go in both directions
+    let request_response_params = [
+        // Many round trips with moderately sized responses
+        (100, 1024, 16 * 1024),
+        // HTTP-like load, with small requests and small responses
+        (100, 200, 2048),
+    ];
+    for (round_trips, request_size, response_size) in request_response_params {
+        benchmarks.push(
+            Benchmark::new(
+                format!("request_response_{round_trips}x_{request_size}_{response_size}_{tls}"),
+                move || {
+                    bench_request_response(
+                        &black_box(get_param()),
+                        black_box(round_trips),
+                        black_box(request_size),
+                        black_box(response_size),
+                    )
+                },
+            )
+            .exclude_setup_instructions(format!("established_conn_{tls}")),
+        );
+    }
+}
+
+fn main() {
+    let params = [
+        || {
+            BenchmarkParam::new(
+                KeyType::Rsa,
+                rustls::cipher_suite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+                &rustls::version::TLS12,
+            )
+        },
+        || {
+            BenchmarkParam::new(
+                KeyType::Rsa,
+                rustls::cipher_suite::TLS13_AES_128_GCM_SHA256,
+                &rustls::version::TLS13,
+            )
+        },
+    ];
+
+    // Recording transcripts is done once, outside of cachegrind, and the resulting fixtures are
+    // reused across runs so different rustls versions can be compared against the same input
+    if std::env::args().nth(1).as_deref() == Some("--record-transcripts") {
+        for param in params {
+            let transcript = transcript_path(&format!("{:?}", param().version));
+            record_handshake(&param()).save(&transcript).unwrap();
+            println!("Recorded {}", transcript.display());
+        }
+        return;
+    }
+
+    // Like transcripts, the corpus is generated once and then reused across runs
+    if std::env::args().nth(1).as_deref() == Some("--generate-corpus") {
+        let inputs = generate_corpus(&params);
+        corpus::save(&corpus_dir(), &inputs).unwrap();
+        println!(
+            "Generated {} inputs in {}",
+            inputs.len(),
+            corpus_dir().display()
+        );
+        return;
+    }
+
+    // The wall-clock scenario runs outside of cachegrind, because it is about contention between
+    // threads rather than about the amount of work done
+    if std::env::args().nth(1).as_deref() == Some("--wall-clock") {
+        for param in params {
+            run_wall_clock(&param());
+        }
+        return;
+    }
 
     let mut benchmarks = Vec::new();
     for param in params {
@@ -0,0 +1,91 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Direction {
+    ClientToServer,
+    ServerToClient,
+}
+
+impl Direction {
+    fn to_byte(self) -> u8 {
+        match self {
+            Self::ClientToServer => 0,
+            Self::ServerToClient => 1,
+        }
+    }
+
+    fn from_byte(byte: u8) -> io::Result<Self> {
+        match byte {
+            0 => Ok(Self::ClientToServer),
+            1 => Ok(Self::ServerToClient),
+            _ => Err(invalid_data("unknown flight direction")),
+        }
+    }
+}
+
+/// The TLS bytes exchanged by a client and a server, grouped in flights
+///
+/// Transcripts are stored as a sequence of flights, each consisting of a direction byte, the
+/// length of the flight as a big-endian `u32` and the flight's bytes.
+#[derive(Default)]
+pub struct Transcript {
+    flights: Vec<(Direction, Vec<u8>)>,
+}
+
+impl Transcript {
+    pub fn push(&mut self, direction: Direction, flight: Vec<u8>) {
+        self.flights.push((direction, flight));
+    }
+
+    /// Returns the first flight sent in the provided direction, if any
+    pub fn first_flight(&self, direction: Direction) -> Option<&[u8]> {
+        self.flights
+            .iter()
+            .find(|(d, _)| *d == direction)
+            .map(|(_, flight)| flight.as_slice())
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        for (direction, flight) in &self.flights {
+            bytes.push(direction.to_byte());
+            bytes.extend_from_slice(&(flight.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(flight);
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, bytes)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let mut rest = bytes.as_slice();
+        let mut transcript = Self::default();
+
+        while let Some((&direction, tail)) = rest.split_first() {
+            if tail.len() < 4 {
+                return Err(invalid_data("truncated flight length"));
+            }
+
+            let (len, tail) = tail.split_at(4);
+            let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+            if tail.len() < len {
+                return Err(invalid_data("truncated flight"));
+            }
+
+            let (flight, tail) = tail.split_at(len);
+            transcript.push(Direction::from_byte(direction)?, flight.to_vec());
+            rest = tail;
+        }
+
+        Ok(transcript)
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,335 @@
+use std::io::{self, IoSlice, Read, Write};
+use std::ops::{Deref, DerefMut};
+
+use rustls::{ConnectionCommon, SideData};
+
+use crate::bench_lib;
+
+/// Amount of TLS data buffered before it is handed over to the receiving connection
+const PIPE_CAPACITY: usize = 262144;
+
+/// Size of the scratch buffer used to drain plaintext from the receiving connection
+const PLAINTEXT_BUF_SIZE: usize = 8192;
+
+/// An in-memory byte pipe backed by a persistent buffer
+///
+/// Bytes written to the pipe are read back in the same order. The buffer is reset (but not
+/// deallocated) once all bytes have been read, so a pipe can be reused without allocating. Writes
+/// never grow the buffer: they are cut short once it is full.
+pub struct Pipe {
+    buf: Vec<u8>,
+    read_offset: usize,
+    capacity: usize,
+}
+
+impl Pipe {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            buf: Vec::with_capacity(capacity),
+            read_offset: 0,
+            capacity,
+        }
+    }
+
+    /// Returns the number of bytes that can still be written before the buffer is full
+    fn space(&self) -> usize {
+        self.capacity - self.buf.len()
+    }
+
+    /// Returns the number of bytes that have been written but not yet read
+    pub fn len(&self) -> usize {
+        self.buf.len() - self.read_offset
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Discards all bytes that have not been read yet
+    pub fn clear(&mut self) {
+        self.buf.clear();
+        self.read_offset = 0;
+    }
+}
+
+impl Read for Pipe {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let available = &self.buf[self.read_offset..];
+        let read = available.len().min(out.len());
+        out[..read].copy_from_slice(&available[..read]);
+
+        self.read_offset += read;
+        if self.read_offset == self.buf.len() {
+            self.buf.clear();
+            self.read_offset = 0;
+        }
+
+        Ok(read)
+    }
+}
+
+impl Write for Pipe {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let written = data.len().min(self.space());
+        self.buf.extend_from_slice(&data[..written]);
+        Ok(written)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let mut written = 0;
+        for buf in bufs {
+            let len = buf.len().min(self.space());
+            self.buf.extend_from_slice(&buf[..len]);
+            written += len;
+            if len < buf.len() {
+                break;
+            }
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Hands the TLS data written by one connection straight to another connection's `read_tls`
+struct Direct<'a, C>(&'a mut C);
+
+impl<C, S> Write for Direct<'_, C>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<S>>,
+    S: SideData,
+{
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.0.read_tls(&mut &data[..])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Completes the handshake over a blocking socket, using the connection's own `complete_io`
+///
+/// `complete_io` returns as soon as the handshake is done, which may leave the connection's final
+/// flight unsent, so pending data is written out afterwards.
+pub fn complete_handshake<C, S>(conn: &mut C, sock: &mut (impl Read + Write))
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<S>>,
+    S: SideData,
+{
+    while conn.is_handshaking() {
+        conn.complete_io(sock).unwrap();
+    }
+
+    while conn.wants_write() {
+        conn.complete_io(sock).unwrap();
+    }
+}
+
+/// The connections whose work is counted in `Transport::transfer_measured`
+#[derive(PartialEq, Clone, Copy)]
+pub enum Measured {
+    /// Instrumentation is left untouched
+    Both,
+    /// Only the work done by the left connection is counted
+    Left,
+    /// Only the work done by the right connection is counted
+    Right,
+}
+
+/// Moves TLS data between connections, reusing its buffers across transfers
+///
+/// The buffers are allocated once, when the transport is created, so the transfers themselves add
+/// as few instructions as possible to the measurement. A benchmark that creates a transport in its
+/// measured region needs a setup benchmark that creates one too, so subtracting the setup cancels
+/// out the allocation.
+pub struct Transport {
+    /// TLS data written by the left connection and not yet read by the right one
+    pipe: Pipe,
+    /// Scratch space for draining plaintext from the right connection
+    plaintext: Vec<u8>,
+}
+
+impl Transport {
+    pub fn new() -> Self {
+        Self {
+            pipe: Pipe::with_capacity(PIPE_CAPACITY),
+            plaintext: vec![0; PLAINTEXT_BUF_SIZE],
+        }
+    }
+
+    /// Sends all pending TLS data from `left` to `right`, returning the amount of bytes sent
+    ///
+    /// If `expect_data` is provided, `right` is expected to receive that many bytes of plaintext,
+    /// which are read and discarded.
+    pub fn transfer<L, R, LS, RS>(
+        &mut self,
+        left: &mut L,
+        right: &mut R,
+        expect_data: Option<usize>,
+    ) -> usize
+    where
+        L: DerefMut + Deref<Target = ConnectionCommon<LS>>,
+        R: DerefMut + Deref<Target = ConnectionCommon<RS>>,
+        LS: SideData,
+        RS: SideData,
+    {
+        self.transfer_measured(left, right, expect_data, Measured::Both)
+    }
+
+    /// Like `transfer`, but without the intermediate pipe
+    ///
+    /// `left` writes its TLS data directly into the receive buffer of `right`, which saves a copy
+    /// of every byte. This is as close to the record layer as the pinned rustls lets us get, so
+    /// comparing it with `transfer` shows how much the pipe costs.
+    pub fn transfer_direct<L, R, LS, RS>(
+        &mut self,
+        left: &mut L,
+        right: &mut R,
+        expect_data: Option<usize>,
+    ) where
+        L: DerefMut + Deref<Target = ConnectionCommon<LS>>,
+        R: DerefMut + Deref<Target = ConnectionCommon<RS>>,
+        LS: SideData,
+        RS: SideData,
+    {
+        let mut data_left = expect_data.unwrap_or(0);
+
+        while left.wants_write() {
+            left.write_tls(&mut Direct(&mut *right)).unwrap();
+            right.process_new_packets().unwrap();
+
+            while data_left > 0 {
+                let sz = match right.reader().read(&mut self.plaintext) {
+                    Ok(sz) => sz,
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(err) => panic!("failed to read data: {}", err),
+                };
+                data_left -= sz;
+            }
+        }
+
+        assert_eq!(data_left, 0);
+    }
+
+    /// Like `transfer`, but allows counting only the instructions of one of the connections
+    ///
+    /// When measuring a single connection, instrumentation must be stopped before calling this.
+    pub fn transfer_measured<L, R, LS, RS>(
+        &mut self,
+        left: &mut L,
+        right: &mut R,
+        expect_data: Option<usize>,
+        measured: Measured,
+    ) -> usize
+    where
+        L: DerefMut + Deref<Target = ConnectionCommon<LS>>,
+        R: DerefMut + Deref<Target = ConnectionCommon<RS>>,
+        LS: SideData,
+        RS: SideData,
+    {
+        let measure_left = measured == Measured::Left;
+        let measure_right = measured == Measured::Right;
+        let mut data_left = expect_data;
+        let mut sent = 0;
+
+        loop {
+            while left.wants_write() && self.pipe.len() < PIPE_CAPACITY {
+                let written =
+                    bench_lib::measure_if(measure_left, || left.write_tls(&mut self.pipe)).unwrap();
+                if written == 0 {
+                    break;
+                }
+                sent += written;
+            }
+
+            if self.pipe.is_empty() {
+                return sent;
+            }
+
+            while !self.pipe.is_empty() {
+                let result = bench_lib::measure_if(measure_right, || {
+                    right.read_tls(&mut self.pipe)?;
+                    right.process_new_packets().unwrap();
+                    Ok::<_, io::Error>(())
+                });
+                if let Err(err) = result {
+                    panic!(
+                        "error on transfer ({} bytes left): {}",
+                        self.pipe.len(),
+                        err
+                    );
+                }
+
+                if let Some(left) = &mut data_left {
+                    loop {
+                        let result = bench_lib::measure_if(measure_right, || {
+                            right.reader().read(&mut self.plaintext)
+                        });
+                        let sz = match result {
+                            Ok(sz) => sz,
+                            Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                            Err(err) => panic!("failed to read data: {}", err),
+                        };
+
+                        *left -= sz;
+                        if *left == 0 {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Like `transfer_measured`, but hands the errors reported by `right` to the caller instead of
+    /// panicking, for handshakes that are expected to fail
+    ///
+    /// Returns the amount of bytes sent by `left`, along with the outcome of processing them. Once
+    /// `right` reports an error, the rest of the data is discarded, since a failed connection
+    /// processes no more packets. No plaintext is read.
+    pub fn try_transfer_measured<L, R, LS, RS>(
+        &mut self,
+        left: &mut L,
+        right: &mut R,
+        measured: Measured,
+    ) -> (usize, Result<(), rustls::Error>)
+    where
+        L: DerefMut + Deref<Target = ConnectionCommon<LS>>,
+        R: DerefMut + Deref<Target = ConnectionCommon<RS>>,
+        LS: SideData,
+        RS: SideData,
+    {
+        let measure_left = measured == Measured::Left;
+        let measure_right = measured == Measured::Right;
+        let mut sent = 0;
+
+        loop {
+            while left.wants_write() && self.pipe.len() < PIPE_CAPACITY {
+                let written =
+                    bench_lib::measure_if(measure_left, || left.write_tls(&mut self.pipe)).unwrap();
+                if written == 0 {
+                    break;
+                }
+                sent += written;
+            }
+
+            if self.pipe.is_empty() {
+                return (sent, Ok(()));
+            }
+
+            while !self.pipe.is_empty() {
+                let result = bench_lib::measure_if(measure_right, || {
+                    right.read_tls(&mut self.pipe).unwrap();
+                    right.process_new_packets().map(|_| ())
+                });
+                if let Err(err) = result {
+                    self.pipe.clear();
+                    return (sent, Err(err));
+                }
+            }
+        }
+    }
+}
@@ -156,6 +156,19 @@ for kt in rsa ecdsa eddsa ; do
             -set_serial 456 \
             -extensions v3_end -extfile openssl.cnf
 
+  # An end-entity certificate that has long expired, used to benchmark rejected handshakes.
+  # Setting explicit validity dates requires OpenSSL 3.4 or later.
+  openssl x509 -req \
+            -in $kt/end.req \
+            -out $kt/end.expired.cert \
+            -CA $kt/inter.cert \
+            -CAkey $kt/inter.key \
+            -sha256 \
+            -not_before 20200101000000Z \
+            -not_after 20210101000000Z \
+            -set_serial 457 \
+            -extensions v3_end -extfile openssl.cnf
+
   openssl x509 -req \
             -in $kt/client.req \
             -out $kt/client.cert \
@@ -195,6 +208,7 @@ for kt in rsa ecdsa eddsa ; do
 
   cat $kt/inter.cert $kt/ca.cert > $kt/end.chain
   cat $kt/end.cert $kt/inter.cert $kt/ca.cert > $kt/end.fullchain
+  cat $kt/end.expired.cert $kt/inter.cert $kt/ca.cert > $kt/end.expired.fullchain
 
   cat $kt/inter.cert $kt/ca.cert > $kt/client.chain
   cat $kt/client.cert $kt/inter.cert $kt/ca.cert > $kt/client.fullchain